@@ -2,11 +2,11 @@
 
 if [%1]==[--release] (
   set opt=%1
-  set target_dir=target\release
+  set target_dir=..\target\release
   set args=%2 %3 %4 %5 %6 %7 %8 %9
 ) else (
   set opt=
-  set target_dir=target\debug
+  set target_dir=..\target\debug
   set args=%*
 )
 
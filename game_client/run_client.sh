@@ -1,7 +1,7 @@
 #!/bin/sh
 
 target_dir="${CARGO_TARGET_DIR}"
-[ -z "${target_dir}" ] && target_dir="./target"
+[ -z "${target_dir}" ] && target_dir="../target"
 
 if [ "${1}" = "--release" ] ; then
   opt="${1}"
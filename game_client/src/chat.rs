@@ -0,0 +1,126 @@
+use game_protocol::{ClientMessage, RoomRequest, MAX_CHAT_LEN};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Nombre de messages conservés dans le journal affiché
+const MAX_LINES: usize = 8;
+
+// Un message reste affiché pendant `DISPLAY`, en s'effaçant progressivement
+// pendant les dernières `FADE`
+const DISPLAY: Duration = Duration::from_secs(10);
+const FADE: Duration = Duration::from_secs(2);
+
+// Journal des messages de discussion et message en cours de saisie ; la
+// touche Entrée ouvre la saisie, qui capture alors le clavier
+#[derive(Debug)]
+pub struct ChatLog {
+    lines: VecDeque<(Instant, String)>, // Messages reçus, du plus ancien au plus récent
+    draft: Option<String>, // Message en cours de saisie, s'il y en a un
+}
+
+impl ChatLog {
+    pub fn new() -> Self {
+        ChatLog {
+            lines: VecDeque::new(),
+            draft: None,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.lines.push_back((Instant::now(), line));
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.draft.is_some()
+    }
+
+    pub fn draft(&self) -> Option<&str> {
+        self.draft.as_deref()
+    }
+
+    pub fn start_typing(&mut self) {
+        self.draft = Some(String::new());
+    }
+
+    // Touche pressée pendant la saisie ; retourne le message à envoyer
+    // lorsque Entrée le valide. Échap abandonne la saisie
+    pub fn key(&mut self, key: &str) -> Option<String> {
+        let draft = self.draft.as_mut()?;
+        match key {
+            "\n" => {
+                let text = self.draft.take()?;
+                let text = text.trim();
+                return (!text.is_empty()).then(|| text.to_string());
+            }
+            "Escape" => self.draft = None,
+            "\x08" | "BackSpace" => {
+                draft.pop();
+            }
+            _ => {
+                // Les touches sans caractère (flèches, Maj...) sont ignorées
+                let mut chars = key.chars();
+                if let (Some(c), None) = (chars.next(), chars.next()) {
+                    if !c.is_control() && draft.chars().count() < MAX_CHAT_LEN
+                    {
+                        draft.push(c);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Messages encore affichés, avec leur opacité (de 0 à 1)
+    pub fn visible(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.lines.iter().filter_map(|(received, line)| {
+            let remaining = DISPLAY.checked_sub(received.elapsed())?;
+            let opacity = remaining.as_secs_f32() / FADE.as_secs_f32();
+            Some((line.as_str(), opacity.min(1.0)))
+        })
+    }
+
+    // Des messages sont affichés : l'écran doit être redessiné pour qu'ils
+    // s'effacent progressivement
+    pub fn is_showing(&self) -> bool {
+        self.lines
+            .iter()
+            .any(|(received, _)| received.elapsed() < DISPLAY)
+    }
+}
+
+// Message à envoyer pour un texte validé : les commandes commençant par `/`
+// gèrent les salons, le reste est un message de discussion. Une commande
+// inconnue ou mal formée retourne l'aide à afficher
+pub fn message_for(text: String) -> Result<ClientMessage, String> {
+    if !text.starts_with('/') {
+        return Ok(ClientMessage::Chat(text));
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    match words.as_slice() {
+        ["/salons"] => Ok(ClientMessage::ListRooms),
+        ["/creer", name] => Ok(ClientMessage::CreateRoom(RoomRequest {
+            name: name.to_string(),
+            width: None,
+            height: None,
+        })),
+        ["/creer", name, size] => {
+            let (width, height) = size
+                .split_once(['x', '×'])
+                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                .ok_or(format!("taille « {} » invalide, LxH attendu", size))?;
+            Ok(ClientMessage::CreateRoom(RoomRequest {
+                name: name.to_string(),
+                width: Some(width),
+                height: Some(height),
+            }))
+        }
+        ["/rejoindre", name] => Ok(ClientMessage::JoinRoom(name.to_string())),
+        ["/quitter"] => Ok(ClientMessage::LeaveRoom),
+        _ => Err("commandes : /salons, /creer nom [LxH], /rejoindre nom, \
+                  /quitter"
+            .to_string()),
+    }
+}
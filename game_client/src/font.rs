@@ -0,0 +1,315 @@
+use crate::Screen;
+use game_protocol::{Color, Point};
+
+// Police bitmap 5×7 pour les caractères ASCII imprimables : chaque glyphe
+// est une suite de colonnes de gauche à droite, le bit 0 étant la ligne du
+// haut
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+
+// Espace entre deux caractères et entre deux lignes
+const SPACING: usize = 1;
+const LINE_SPACING: usize = 3;
+
+// Largeur occupée par un caractère et hauteur d'une ligne de texte
+pub const ADVANCE: usize = GLYPH_WIDTH + SPACING;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + LINE_SPACING;
+
+// Marge entre le texte et le bord de son fond
+pub const PADDING: usize = 2;
+
+pub const WHITE: Color = Color {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+
+// Apparence d'un texte ; l'opacité s'applique au texte, à son ombre et à son
+// fond, pour les faire disparaître progressivement
+#[derive(Debug, Clone, Copy)]
+pub struct TextStyle {
+    pub color: Color,
+    pub shadow: Option<Color>, // Ombre décalée d'un pixel vers le bas et la droite
+    pub background: Option<(Color, f32)>, // Fond rectangulaire et son opacité propre
+    pub opacity: f32, // De 0 (invisible) à 1 (opaque)
+}
+
+impl TextStyle {
+    // Texte opaque de la couleur donnée, sans ombre ni fond
+    pub fn plain(color: Color) -> Self {
+        TextStyle {
+            color,
+            shadow: None,
+            background: None,
+            opacity: 1.0,
+        }
+    }
+}
+
+const GLYPHS: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '\''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x14, 0x08, 0x3E, 0x08, 0x14], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x08, 0x54, 0x54, 0x54, 0x3C], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
+
+// Glyphe d'un caractère ; les lettres accentuées perdent leur accent et les
+// autres caractères inconnus s'affichent comme '?'
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH] {
+    let c = match c {
+        'à' | 'â' | 'ä' => 'a',
+        'ç' => 'c',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'î' | 'ï' => 'i',
+        'ô' | 'ö' => 'o',
+        'ù' | 'û' | 'ü' => 'u',
+        'À' | 'Â' | 'Ä' => 'A',
+        'Ç' => 'C',
+        'É' | 'È' | 'Ê' | 'Ë' => 'E',
+        'Î' | 'Ï' => 'I',
+        'Ô' | 'Ö' => 'O',
+        'Ù' | 'Û' | 'Ü' => 'U',
+        ' '..='~' => c,
+        _ => '?',
+    };
+    &GLYPHS[c as usize - ' ' as usize]
+}
+
+// Largeur et hauteur en pixels d'un texte, éventuellement sur plusieurs
+// lignes séparées par '\n' (sans le fond)
+pub fn text_size(text: &str) -> (usize, usize) {
+    let width = text
+        .lines()
+        .map(|line| (line.chars().count() * ADVANCE).saturating_sub(SPACING))
+        .max()
+        .unwrap_or(0);
+    let lines = text.lines().count();
+    let height = (lines * LINE_HEIGHT).saturating_sub(LINE_SPACING);
+    (width, height)
+}
+
+// Découper un texte en lignes d'au plus `max_width` pixels, entre les mots
+// si possible ; un mot trop long est coupé
+pub fn wrap(text: &str, max_width: usize) -> Vec<String> {
+    let columns = ((max_width + SPACING) / ADVANCE).max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut len = 0;
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        if len > 0 && len + 1 + word.len() > columns {
+            lines.push(std::mem::take(&mut line));
+            len = 0;
+        }
+        if len > 0 {
+            line.push(' ');
+            len += 1;
+        }
+        while len + word.len() > columns {
+            let rest = word.split_off(columns - len);
+            line.extend(word);
+            lines.push(std::mem::take(&mut line));
+            len = 0;
+            word = rest;
+        }
+        len += word.len();
+        line.extend(word);
+    }
+    if len > 0 || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Écrire un texte dont le coin supérieur gauche est `position`, sur son fond
+// s'il en a un (qui déborde alors de `PADDING` pixels) ; les pixels hors de
+// l'écran sont ignorés. Retourne la taille du texte, comme `text_size`
+pub fn draw_text(
+    screen: &mut Screen,
+    text: &str,
+    position: Point,
+    style: &TextStyle,
+) -> (usize, usize) {
+    let size = text_size(text);
+    if let Some((color, opacity)) = style.background {
+        let corner = Point {
+            x: position.x - PADDING as i32,
+            y: position.y - PADDING as i32,
+        };
+        let (width, height) = (size.0 + 2 * PADDING, size.1 + 2 * PADDING);
+        let opacity = opacity * style.opacity;
+        fill_rect(screen, corner, width, height, color, opacity);
+    }
+    if let Some(shadow) = style.shadow {
+        let offset = Point {
+            x: position.x + 1,
+            y: position.y + 1,
+        };
+        draw_glyphs(screen, text, offset, shadow, style.opacity);
+    }
+    draw_glyphs(screen, text, position, style.color, style.opacity);
+    size
+}
+
+// Remplir un rectangle, mélangé à l'image existante selon `opacity`
+pub fn fill_rect(
+    screen: &mut Screen,
+    corner: Point,
+    width: usize,
+    height: usize,
+    color: Color,
+    opacity: f32,
+) {
+    let x0 = (corner.x as i64).max(0);
+    let y0 = (corner.y as i64).max(0);
+    let x1 = (corner.x as i64 + width as i64).min(screen.width as i64);
+    let y1 = (corner.y as i64 + height as i64).min(screen.height as i64);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            plot(screen, x, y, color, opacity);
+        }
+    }
+}
+
+fn draw_glyphs(
+    screen: &mut Screen,
+    text: &str,
+    position: Point,
+    color: Color,
+    opacity: f32,
+) {
+    for (row, line) in text.lines().enumerate() {
+        let top = position.y as i64 + (row * LINE_HEIGHT) as i64;
+        if top >= screen.height as i64 || top + (GLYPH_HEIGHT as i64) < 0 {
+            continue;
+        }
+        for (i, c) in line.chars().enumerate() {
+            let left = position.x as i64 + (i * ADVANCE) as i64;
+            if left >= screen.width as i64 {
+                break;
+            }
+            for (dx, column) in glyph(c).iter().enumerate() {
+                for dy in 0..GLYPH_HEIGHT {
+                    if column & (1 << dy) != 0 {
+                        let (x, y) = (left + dx as i64, top + dy as i64);
+                        plot(screen, x, y, color, opacity);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Colorer un pixel s'il est dans l'écran
+fn plot(screen: &mut Screen, x: i64, y: i64, color: Color, opacity: f32) {
+    if x < 0 || y < 0 || x >= screen.width as i64 || y >= screen.height as i64
+    {
+        return;
+    }
+    let pixel = &mut screen.pixels[y as usize * screen.width + x as usize];
+    if opacity >= 1.0 {
+        *pixel = color;
+        return;
+    }
+    let opacity = opacity.max(0.0);
+    let mix = |from: u8, to: u8| {
+        (from as f32 + (to as f32 - from as f32) * opacity).round() as u8
+    };
+    *pixel = Color {
+        r: mix(pixel.r, color.r),
+        g: mix(pixel.g, color.g),
+        b: mix(pixel.b, color.b),
+    };
+}
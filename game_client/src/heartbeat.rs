@@ -0,0 +1,49 @@
+use game_protocol::HeartbeatPolicy;
+use std::time::{Duration, Instant};
+
+// Surveillance de la connexion au serveur, au rythme annoncé dans `welcome` :
+// un `ping` part à chaque intervalle et la connexion est perdue si rien
+// n'arrive pendant `misses` intervalles
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+    seq: u64, // Numéro du dernier `ping` envoyé
+    last_ping: Instant,
+    last_received: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(policy: HeartbeatPolicy) -> Self {
+        let interval = Duration::from_millis(policy.interval_ms);
+        let now = Instant::now();
+        Heartbeat {
+            interval,
+            timeout: interval * policy.misses,
+            seq: 0,
+            last_ping: now,
+            last_received: now,
+        }
+    }
+
+    // Un message du serveur est arrivé
+    pub fn received(&mut self) {
+        self.last_received = Instant::now();
+    }
+
+    // Temps écoulé sans nouvelles du serveur, s'il dépasse la limite
+    pub fn lost(&self) -> Option<Duration> {
+        let silent = self.last_received.elapsed();
+        (silent > self.timeout).then_some(silent)
+    }
+
+    // Numéro du `ping` à envoyer, si l'intervalle est écoulé
+    pub fn next_ping(&mut self) -> Option<u64> {
+        if self.last_ping.elapsed() < self.interval {
+            return None;
+        }
+        self.last_ping = Instant::now();
+        self.seq += 1;
+        Some(self.seq)
+    }
+}
@@ -0,0 +1,98 @@
+use game_protocol::Point;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+// Les autres joueurs sont affichés avec ce retard sur l'horloge du serveur,
+// pour avoir presque toujours deux positions reçues qui encadrent l'instant
+// dessiné
+const INTERPOLATION_DELAY_MS: f64 = 100.0;
+
+// Au-delà de la dernière position reçue, prolonger le mouvement au plus
+// pendant cette durée puis rester immobile en attendant le serveur
+const MAX_EXTRAPOLATION_MS: f64 = 150.0;
+
+// Durée d'historique conservée pour chaque joueur
+const HISTORY_MS: u64 = 1000;
+
+// Historique horodaté (horloge du serveur) des positions des autres joueurs
+#[derive(Debug)]
+pub struct Interpolator {
+    started: Instant,
+    // Écart estimé entre l'horloge locale et celle du serveur (ms) ; il
+    // inclut le délai de transmission le plus court observé
+    clock_offset: Option<f64>,
+    histories: HashMap<u32, VecDeque<(u64, Point)>>,
+}
+
+impl Interpolator {
+    pub fn new() -> Self {
+        Interpolator {
+            started: Instant::now(),
+            clock_offset: None,
+            histories: HashMap::new(),
+        }
+    }
+
+    fn local_ms(&self) -> f64 {
+        self.started.elapsed().as_secs_f64() * 1000.0
+    }
+
+    // Ajouter les positions d'un instantané daté de `server_time` ; les
+    // joueurs absents de `positions` sont oubliés
+    pub fn record(&mut self, server_time: u64, positions: &HashMap<u32, Point>) {
+        // Garder l'écart le plus faible (message arrivé le plus vite), en le
+        // laissant remonter doucement pour suivre une dérive des horloges
+        let offset = self.local_ms() - server_time as f64;
+        self.clock_offset = Some(match self.clock_offset {
+            Some(previous) => offset.min(previous + 1.0),
+            None => offset,
+        });
+
+        self.histories.retain(|id, _| positions.contains_key(id));
+        for (&id, &position) in positions {
+            let history = self.histories.entry(id).or_default();
+            if history.back().is_some_and(|(time, _)| *time >= server_time) {
+                continue;
+            }
+            history.push_back((server_time, position));
+            while history
+                .front()
+                .is_some_and(|(time, _)| time + HISTORY_MS < server_time)
+            {
+                history.pop_front();
+            }
+        }
+    }
+
+    // Position à afficher maintenant pour le joueur `id`
+    pub fn position(&self, id: u32) -> Option<Point> {
+        let history = self.histories.get(&id)?;
+        let (_, last) = *history.back()?;
+        let Some(offset) = self.clock_offset else {
+            return Some(last);
+        };
+        let render_time = self.local_ms() - offset - INTERPOLATION_DELAY_MS;
+
+        // Interpoler entre les deux positions qui encadrent l'instant dessiné
+        let after = history
+            .iter()
+            .position(|(time, _)| *time as f64 >= render_time);
+        let (from, to) = match after {
+            Some(0) => return Some(history[0].1),
+            Some(i) => (history[i - 1], history[i]),
+            // Plus de position récente : extrapoler depuis les deux
+            // dernières, sur une durée limitée
+            None if history.len() >= 2 => {
+                (history[history.len() - 2], history[history.len() - 1])
+            }
+            None => return Some(last),
+        };
+        let span = (to.0 - from.0) as f64;
+        let render_time = render_time.min(to.0 as f64 + MAX_EXTRAPOLATION_MS);
+        let t = (render_time - from.0 as f64) / span;
+        Some(Point {
+            x: from.1.x + ((to.1.x - from.1.x) as f64 * t).round() as i32,
+            y: from.1.y + ((to.1.y - from.1.y) as f64 * t).round() as i32,
+        })
+    }
+}
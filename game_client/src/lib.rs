@@ -1,10 +1,28 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::BufRead;
-use std::io::BufReader;
+use game_protocol::{
+    apply_motion, check_name, max_position, ClientMessage, Color, Frame,
+    FrameDecoder, Framing, Hello, Image, ImageHash, Motion, Point, Positions,
+    RoomInfo, ServerMessage, ServerShutdown, SessionOffer, Snapshot, Welcome,
+    BINARY_FRAMES, PROTOCOL_VERSION, TRANSPARENT, UDP_CHANNEL,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+mod chat;
+mod font;
+mod heartbeat;
+mod interpolation;
+mod udp;
+
+use chat::ChatLog;
+use font::TextStyle;
+use heartbeat::Heartbeat;
+use interpolation::Interpolator;
+use udp::UdpChannel;
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -85,17 +103,123 @@ fn game_client_update(
         UpdateStatus::Redraw => 1,
         UpdateStatus::Quit => {
             // ensure deallocation
-            let _owned = unsafe { Box::from_raw(app) };
+            let mut owned = unsafe { Box::from_raw(app) };
+            leave(&mut owned);
             -1
         }
     }
 }
 
 //~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-#[derive(Debug)]
+
+// Nombre d'instantanés du monde conservés pour appliquer les deltas du serveur
+// (même profondeur que l'historique du serveur)
+const SNAPSHOT_HISTORY: usize = 32;
+
+// Délai maximal pour joindre le serveur et recevoir `welcome`, pour qu'une
+// tentative de reprise échouée laisse vite place à la suivante
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Intervalle entre deux tentatives de reprise de session
+const RESUME_INTERVAL: Duration = Duration::from_secs(1);
+
+// Durée d'affichage de la raison de la fin de partie avant de quitter
+const CLOSING_NOTICE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
 struct ClientInfo {
     position: Point,
-    image: Image,
+    image: ImageHash, // Empreinte de l'avatar, à chercher dans `ImageCache`
+    name: Option<String>, // Pseudonyme choisi par le joueur, s'il en a un
+}
+
+// Images connues du client, indexées par empreinte ; si un répertoire est
+// configuré (variable GAME_CLIENT_CACHE), elles y sont aussi conservées au
+// format PPM d'une session à l'autre
+#[derive(Debug)]
+struct ImageCache {
+    images: HashMap<ImageHash, Image>,
+    requested: HashSet<ImageHash>, // Demandées au serveur, pas encore reçues
+    directory: Option<PathBuf>,
+}
+
+impl ImageCache {
+    fn new(directory: Option<PathBuf>) -> Self {
+        if let Some(dir) = &directory {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                eprintln!(
+                    "Impossible de créer le cache d'images {:?} : {}",
+                    dir, e
+                );
+            }
+        }
+        ImageCache {
+            images: HashMap::new(),
+            requested: HashSet::new(),
+            directory,
+        }
+    }
+
+    fn get(&self, hash: &ImageHash) -> Option<&Image> {
+        self.images.get(hash)
+    }
+
+    // L'empreinte, vérifiée à la réception, ne peut pas sortir du répertoire
+    fn path(&self, hash: &ImageHash) -> Option<PathBuf> {
+        self.directory
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.ppm", hash)))
+    }
+
+    fn insert(&mut self, image: Image) -> ImageHash {
+        let hash = image.hash();
+        self.requested.remove(&hash);
+        if let Some(path) = self.path(&hash) {
+            if !path.exists() {
+                if let Err(e) = save_image(&path, &image) {
+                    eprintln!(
+                        "Échec de l'enregistrement de l'image {} : {}",
+                        hash, e
+                    );
+                }
+            }
+        }
+        self.images.insert(hash.clone(), image);
+        hash
+    }
+
+    // Rendre l'image disponible en mémoire (depuis le disque si possible),
+    // sinon la demander au serveur une seule fois
+    fn ensure(
+        &mut self,
+        hash: &ImageHash,
+        output: Option<&mut TcpStream>,
+        framing: Framing,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.images.contains_key(hash) || self.requested.contains(hash) {
+            return Ok(());
+        }
+        if let Some(path) = self.path(hash).filter(|p| p.exists()) {
+            match load_image(&path.to_string_lossy()) {
+                Ok(image) if image.hash() == *hash => {
+                    println!("Image {} trouvée dans le cache disque", hash);
+                    self.images.insert(hash.clone(), image);
+                    return Ok(());
+                }
+                Ok(_) => eprintln!("Image {} corrompue dans le cache", hash),
+                Err(e) => eprintln!("Image {} illisible : {}", hash, e),
+            }
+        }
+        if let Some(output) = output {
+            output.write_all(
+                &ClientMessage::GetImage(hash.clone()).encode(framing)?,
+            )?;
+            output.flush()?;
+            self.requested.insert(hash.clone());
+            println!("Image {} demandée au serveur", hash);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -112,33 +236,86 @@ enum UpdateStatus {
     Quit,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
-struct Point {
-    x: i32,
-    y: i32,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-}
-
 #[derive(Debug)]
 struct Application {
     status: UpdateStatus,
+    client_id: u32, // Identifiant attribué par le serveur dans `welcome`
+    world_width: usize,
+    world_height: usize,
+    room: Option<String>, // Nom du salon où se trouve notre personnage
     output: Option<TcpStream>,
     input: Option<TcpStream>,
+    framing: Framing, // Format des messages négocié avec le serveur
+    decoder: FrameDecoder,
     clients: HashMap<u32, ClientInfo>,
+    snapshots: VecDeque<(u64, HashMap<u32, ClientInfo>)>, // États reçus, du plus ancien au plus récent
+    images: ImageCache,
+    next_input: u32, // Numéro du dernier déplacement envoyé
+    pending_inputs: VecDeque<Motion>, // Déplacements pas encore pris en compte par le serveur
+    predicted: Option<Point>, // Position anticipée de notre personnage
+    interpolator: Interpolator, // Positions passées des autres joueurs
+    udp: Option<UdpChannel>, // Canal UDP pour les positions, si le serveur l'a proposé
+    heartbeat: Option<Heartbeat>, // Détection d'une connexion perdue, si le serveur la propose
+    server: String, // Adresse du serveur, pour se reconnecter
+    session: Option<SessionOffer>, // Session à reprendre après une coupure
+    lost: Option<Instant>, // Début de la coupure en cours
+    closing: Option<(Closing, Instant)>, // Fin de partie affichée avant de quitter, et son début
+    last_attempt: Option<Instant>, // Dernière tentative de reprise
+    attempt: Option<Receiver<Result<Connection, String>>>, // Tentative en cours dans un thread
+    avatar: Option<ImageHash>, // Notre image, à renvoyer si la session a expiré
+    name: Option<String>, // Notre pseudonyme, présenté à chaque connexion
+    chat: ChatLog, // Messages de discussion reçus et message en cours de saisie
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Image {
-    width: usize,
-    height: usize,
-    pixels: Vec<Color>,
+// Raison de la fin de partie, affichée dans la fenêtre avant de quitter
+#[derive(Debug)]
+enum Closing {
+    Lost(String), // Connexion perdue, sans session à reprendre
+    Kicked(String), // Déconnecté par le serveur
+    Shutdown(ServerShutdown), // Arrêt annoncé par le serveur
+}
+
+impl Closing {
+    // Texte de l'avis, `since` étant le moment de la fin de partie
+    fn text(&self, since: Instant) -> String {
+        match self {
+            Closing::Lost(reason) => format!("Connexion perdue : {}", reason),
+            Closing::Kicked(reason) => {
+                format!("Déconnecté par le serveur : {}", reason)
+            }
+            Closing::Shutdown(notice) => {
+                let mut text = format!("Arrêt du serveur : {}", notice.reason);
+                if let Some(seconds) = notice.restart_in {
+                    let elapsed = since.elapsed().as_secs();
+                    let left = seconds.saturating_sub(elapsed);
+                    text += &format!("\nRedémarrage prévu dans {} s", left);
+                }
+                text
+            }
+        }
+    }
 }
+
+impl Application {
+    // Demander un nouveau rendu sans annuler une demande de fermeture
+    fn redraw(&mut self) {
+        if let UpdateStatus::GoOn = self.status {
+            self.status = UpdateStatus::Redraw;
+        }
+    }
+
+    // Position maximale de notre personnage, avec la même règle que le
+    // serveur : l'avatar qu'il nous connaît reste entier dans le monde
+    fn max_position(&self) -> Point {
+        let sprite = self
+            .clients
+            .get(&self.client_id)
+            .and_then(|me| self.images.get(&me.image));
+        let (width, height) = sprite.map_or((0, 0), |s| (s.width, s.height));
+        max_position(self.world_width, self.world_height, width, height)
+    }
+}
+
 fn init_application(
     args: &[&str],
     width: &mut usize,
@@ -146,8 +323,6 @@ fn init_application(
     dt: &mut f64,
 ) -> Result<Application, Box<dyn std::error::Error>> {
     println!("args: {:?}", args);
-    *width = 800;
-    *height = 600;
     *dt = 1.0 / 30.0;
 
     // Ajoute le chemin de l'image à la structure
@@ -159,17 +334,40 @@ fn init_application(
         );
     };
 
+    let name = nickname(args)?;
+
     // Initialisation de la connexion au serveur
-    let (mut output, input) = init_server(args)?;
+    let server = server_address(args)?;
+    let (mut output, input, decoder, welcome) =
+        connect(&server, None, name.as_deref())?;
+    let framing = Framing::from_capabilities(&welcome.capabilities);
+    println!(
+        "Connecté en tant que client {} (serveur v{}, monde {}×{}, {:?})",
+        welcome.client_id,
+        welcome.server_version,
+        welcome.world_width,
+        welcome.world_height,
+        framing
+    );
+    *width = welcome.world_width;
+    *height = welcome.world_height;
+
+    let udp = open_udp(&input, &welcome);
 
     let clients = std::collections::HashMap::new();
+    let mut images = ImageCache::new(
+        std::env::var_os("GAME_CLIENT_CACHE").map(PathBuf::from),
+    );
+    let mut avatar = None;
     if let Ok(image) = load_image(&image_path) {
         println!("Image chargée avec succès.");
 
-        match serde_json::to_string(&image) {
-            Ok(json_image) => {
-                let msg = format!("image {}\n", json_image);
-                output.write_all(msg.as_bytes())?;
+        // Notre propre avatar n'aura jamais à être demandé au serveur
+        avatar = Some(images.insert(image.clone()));
+
+        match ClientMessage::Image(image).encode(framing) {
+            Ok(msg) => {
+                output.write_all(&msg)?;
                 output.flush()?;
                 println!("Image envoyée au serveur.");
             }
@@ -188,12 +386,55 @@ fn init_application(
 
     Ok(Application {
         status: UpdateStatus::GoOn,
+        client_id: welcome.client_id,
+        world_width: welcome.world_width,
+        world_height: welcome.world_height,
+        room: welcome.room,
         output: Some(output),
         input: Some(input),
+        framing,
+        decoder,
         clients,
+        snapshots: VecDeque::new(),
+        images,
+        next_input: 0,
+        pending_inputs: VecDeque::new(),
+        predicted: None,
+        interpolator: Interpolator::new(),
+        udp,
+        heartbeat: welcome.heartbeat.map(Heartbeat::new),
+        server,
+        session: welcome.session,
+        lost: None,
+        closing: None,
+        last_attempt: None,
+        attempt: None,
+        avatar,
+        name,
+        chat: ChatLog::new(),
     })
 }
 
+// Canal UDP proposé dans `welcome`, s'il peut être ouvert
+fn open_udp(input: &TcpStream, welcome: &Welcome) -> Option<UdpChannel> {
+    let offer = welcome.udp?;
+    let udp = input
+        .peer_addr()
+        .map_err(|e| e.into())
+        .and_then(|server| UdpChannel::open(server, offer, welcome.client_id));
+    match udp {
+        Ok(udp) => {
+            println!("Canal UDP proposé sur le port {}", offer.port);
+            Some(udp)
+        }
+        Err(e) => {
+            // Tout continue de passer par TCP
+            eprintln!("Impossible d'ouvrir le canal UDP : {}", e);
+            None
+        }
+    }
+}
+
 fn update_application(
     evt: &str,
     key: &str,
@@ -219,55 +460,136 @@ fn update_application(
         c.b = 0;
     }
 
-    // Gérer l'événement et envoyer au serveur
-    if let Some(motion) = handle_event(app, evt, key) {
-        println!("motion: {:?}", motion);
-        if let Some(output) = app.output.as_mut() {
-            // Sérialiser motion
-            match serde_json::to_string(&motion) {
-                Ok(json_motion) => {
-                    println!("json_serialized_motion: {:?}", json_motion);
-                    let msg = format!("motion {}\n", json_motion);
-                    output.write_all(msg.as_bytes())?;
-                    output.flush()?;
-                    app.status = UpdateStatus::Redraw;
-                }
-                Err(e) => {
-                    eprintln!("Erreur lors de la sérialisation JSON du mouvement : {}", e);
-                }
+    // Gérer l'événement et envoyer au serveur ; pendant une coupure ou
+    // après l'annonce d'un arrêt, les déplacements et les messages sont
+    // ignorés
+    let connected = app.lost.is_none() && app.closing.is_none();
+    let action = handle_event(app, evt, key).filter(|_| connected);
+    let network = match action {
+        Some(Action::Move(delta)) => send_motion(app, delta),
+        Some(Action::Say(text)) => match chat::message_for(text) {
+            Ok(message) => send_message(app, &message),
+            Err(help) => {
+                app.chat.push(help);
+                app.redraw();
+                Ok(())
             }
-        } else {
-            println!("Aucun flux de sortie disponible pour envoyer la demande au serveur.");
+        },
+        None => Ok(()),
+    }
+    .and_then(|_| match (&app.closing, app.lost) {
+        // Avis d'arrêt affiché jusqu'à la fermeture
+        (Some((_, received)), _) => {
+            if received.elapsed() >= CLOSING_NOTICE {
+                app.status = UpdateStatus::Quit;
+            }
+            app.redraw();
+            Ok(())
+        }
+        (None, Some(_)) => {
+            // Redessiner à chaque fois pour tenir le décompte à jour
+            app.redraw();
+            try_resume(app);
+            Ok(())
         }
+        (None, None) => handle_messages(app),
+    });
+    if let Err(e) = network {
+        connection_lost(app, &e.to_string());
     }
-
-    handle_messages(app)?;
     redraw_if_needed(app, screen);
 
     Ok(app.status)
 }
 
+fn send_motion(
+    app: &mut Application,
+    delta: Point,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("motion: {:?}", delta);
+    let Some(output) = app.output.as_mut() else {
+        println!(
+            "Aucun flux de sortie disponible pour envoyer la demande au serveur."
+        );
+        return Ok(());
+    };
+    let motion = Motion {
+        seq: app.next_input + 1,
+        delta,
+    };
+    // Sérialiser motion ; par UDP, un déplacement perdu sera corrigé par la
+    // réconciliation
+    let message = ClientMessage::Motion(motion);
+    let encoded = match app.udp.as_ref().filter(|udp| udp.is_ready()) {
+        Some(udp) => udp.send(&message).map(|_| None),
+        None => message.encode(app.framing).map(Some),
+    };
+    match encoded {
+        Ok(msg) => {
+            if let Some(msg) = msg {
+                println!("serialized_motion: {:?}", msg);
+                output.write_all(&msg)?;
+                output.flush()?;
+            }
+
+            // Appliquer le déplacement sans attendre la réponse du serveur
+            app.next_input = motion.seq;
+            app.pending_inputs.push_back(motion);
+            let max = app.max_position();
+            app.predicted = app.predicted.map(|p| apply_motion(p, delta, max));
+            app.redraw();
+        }
+        Err(e) => {
+            eprintln!("Erreur lors de l'envoi du mouvement : {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Ce que l'utilisateur demande d'envoyer au serveur
+#[derive(Debug)]
+enum Action {
+    Move(Point),
+    Say(String),
+}
+
 fn handle_event(
     app: &mut Application,
     evt: &str,
     key: &str,
-) -> Option<Point> {
-    let mut motion = None;
+) -> Option<Action> {
+    let mut action = None;
     match evt {
         "C" => app.status = UpdateStatus::Redraw,
+        // Les autres joueurs bougent entre deux instantanés et les messages
+        // de discussion s'effacent peu à peu
+        "T" if app.clients.keys().any(|id| *id != app.client_id)
+            || app.chat.is_showing() =>
+        {
+            app.status = UpdateStatus::Redraw
+        }
         "Q" => app.status = UpdateStatus::Quit,
+        // Pendant la saisie d'un message, le clavier ne déplace plus le chat
+        "KP" if app.chat.is_typing() => {
+            action = app.chat.key(key).map(Action::Say);
+            app.status = UpdateStatus::Redraw;
+        }
         "KP" => match key {
             "Escape" => app.status = UpdateStatus::Quit,
-            "Left" => motion = Some(Point { x: -10, y: 0 }),
-            "Right" => motion = Some(Point { x: 10, y: 0 }),
-            "Up" => motion = Some(Point { x: 0, y: -10 }),
-            "Down" => motion = Some(Point { x: 0, y: 10 }),
+            "Left" => action = Some(Action::Move(Point { x: -10, y: 0 })),
+            "Right" => action = Some(Action::Move(Point { x: 10, y: 0 })),
+            "Up" => action = Some(Action::Move(Point { x: 0, y: -10 })),
+            "Down" => action = Some(Action::Move(Point { x: 0, y: 10 })),
             " " => app.status = UpdateStatus::Redraw,
+            "\n" => {
+                app.chat.start_typing();
+                app.status = UpdateStatus::Redraw;
+            }
             _ => {}
         },
         _ => {}
     }
-    motion
+    action
 }
 
 fn redraw_if_needed(
@@ -275,8 +597,14 @@ fn redraw_if_needed(
     screen: &mut Screen,
 ) {
     if let UpdateStatus::Redraw = app.status {
-        // Effacer l'écran en appliquant une transformation de couleur (exemple arbitraire)
-        for c in screen.pixels.iter_mut() {
+        // Effacer l'écran en appliquant une transformation de couleur (exemple arbitraire),
+        // seulement sur le monde du salon, qui peut être plus petit que la fenêtre
+        let world = screen
+            .pixels
+            .chunks_mut(screen.width)
+            .take(app.world_height)
+            .flat_map(|row| row.iter_mut().take(app.world_width));
+        for c in world {
             let (r, g, b) =
                 (c.r as u32 + 10, c.g as u32 + 25, c.b as u32 + 35);
             c.r = r as u8;
@@ -284,22 +612,53 @@ fn redraw_if_needed(
             c.b = b as u8;
         }
 
-        // Dessiner l'image principale à la position actuelle
-        let transparent_color = Some(Color { r: 0, g: 255, b: 0 });
+        let transparent_color = Some(TRANSPARENT);
 
         // Dessiner les images des autres clients
         for (id, client) in &app.clients {
-            println!(
-                "Dessiner le client {} à la position {:?}",
-                id, client.position
-            );
+            if *id == app.client_id {
+                continue;
+            }
+            // Image pas encore reçue : rien à dessiner pour le moment
+            let Some(image) = app.images.get(&client.image) else {
+                continue;
+            };
+            let position =
+                app.interpolator.position(*id).unwrap_or(client.position);
+            draw_image(screen, image, position, transparent_color);
+            if let Some(name) = &client.name {
+                draw_name(screen, name, image, position);
+            }
+        }
 
-            draw_image(
-                screen,
-                &client.image,
-                client.position,
-                transparent_color,
+        // Dessiner notre propre personnage par-dessus les autres, à sa
+        // position anticipée
+        if let Some(me) = app.clients.get(&app.client_id) {
+            if let Some(image) = app.images.get(&me.image) {
+                let position = app.predicted.unwrap_or(me.position);
+                draw_image(screen, image, position, transparent_color);
+                if let Some(name) = &me.name {
+                    draw_name(screen, name, image, position);
+                }
+            }
+        }
+
+        if let Some(room) = &app.room {
+            draw_room(screen, room);
+        }
+        draw_chat(screen, &app.chat);
+
+        if let (Some(lost), Some(session)) = (app.lost, &app.session) {
+            let grace = Duration::from_millis(session.grace_ms);
+            let left = grace.saturating_sub(lost.elapsed()).as_secs();
+            let text = format!(
+                "Connexion perdue...\nReprise de la session : encore {} s",
+                left
             );
+            draw_notice(screen, &text);
+        }
+        if let Some((closing, since)) = &app.closing {
+            draw_notice(screen, &closing.text(*since));
         }
     }
 }
@@ -363,12 +722,35 @@ fn load_image(path: &str) -> Result<Image, Box<dyn std::error::Error>> {
     })
 }
 
+fn save_image(
+    path: &std::path::Path,
+    image: &Image,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::fmt::Write as _;
+
+    // Même format P3 que celui accepté par `load_image`
+    let mut content = format!("P3\n{} {}\n255\n", image.width, image.height);
+    for row in image.pixels.chunks(image.width.max(1)) {
+        for c in row {
+            write!(content, "{} {} {} ", c.r, c.g, c.b)?;
+        }
+        content.push('\n');
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
 fn draw_image(
     screen: &mut Screen,
     image: &Image,
     position: Point,
     transparent_color: Option<Color>,
 ) {
+    // Une image dont les pixels ne couvrent pas les dimensions ferait sortir
+    // les lignes copiées du tableau
+    if !image.is_consistent() {
+        return;
+    }
     let p0 = Point {
         x: position.x.clamp(0, screen.width as i32),
         y: position.y.clamp(0, screen.height as i32),
@@ -403,18 +785,149 @@ fn draw_image(
     }
 }
 
-fn init_server(
-    args: &[&str]
-) -> Result<(TcpStream, TcpStream), Box<dyn std::error::Error>> {
+// Pseudonyme centré au-dessus d'un avatar dessiné à `position`, en blanc
+// ombré de noir pour rester lisible sur n'importe quel fond
+fn draw_name(screen: &mut Screen, name: &str, image: &Image, position: Point) {
+    let (width, height) = font::text_size(name);
+    let corner = Point {
+        x: position.x + image.width as i32 / 2 - width as i32 / 2,
+        y: position.y - height as i32 - 2,
+    };
+    let style = TextStyle {
+        shadow: Some(font::BLACK),
+        ..TextStyle::plain(font::WHITE)
+    };
+    font::draw_text(screen, name, corner, &style);
+}
+
+// Nom du salon en haut à gauche, sur un fond semi-transparent
+fn draw_room(screen: &mut Screen, room: &str) {
+    let inset = 4 + font::PADDING as i32;
+    let style = TextStyle {
+        background: Some((font::BLACK, 0.5)),
+        ..TextStyle::plain(font::WHITE)
+    };
+    let text = format!("Salon : {}", room);
+    font::draw_text(screen, &text, Point { x: inset, y: inset }, &style);
+}
+
+// Message centré par-dessus le monde assombri, coupé en lignes qui tiennent
+// dans la fenêtre
+fn draw_notice(screen: &mut Screen, text: &str) {
+    const MARGIN: usize = 8;
+    let (width, height) = (screen.width, screen.height);
+    let origin = Point { x: 0, y: 0 };
+    font::fill_rect(screen, origin, width, height, font::BLACK, 0.5);
+    let max_width = width.saturating_sub(2 * (MARGIN + font::PADDING));
+    let lines: Vec<String> = text
+        .lines()
+        .flat_map(|line| font::wrap(line, max_width))
+        .collect();
+    let text = lines.join("\n");
+    let (text_width, text_height) = font::text_size(&text);
+    let corner = Point {
+        x: (width as i32 - text_width as i32) / 2,
+        y: (height as i32 - text_height as i32) / 2,
+    };
+    let style = TextStyle {
+        background: Some((font::BLACK, 0.8)),
+        ..TextStyle::plain(font::WHITE)
+    };
+    font::draw_text(screen, &text, corner, &style);
+}
+
+// Journal de discussion en bas à gauche, au-dessus du message en cours de
+// saisie ; chaque message a son propre fond et les messages trop longs sont
+// coupés sur plusieurs lignes
+fn draw_chat(screen: &mut Screen, chat: &ChatLog) {
+    const MARGIN: usize = 4;
+    let inset = (MARGIN + font::PADDING) as i32;
+    let max_width = screen.width.saturating_sub(2 * (MARGIN + font::PADDING));
+
+    let mut blocks: Vec<(String, f32)> = chat
+        .visible()
+        .map(|(line, opacity)| {
+            (font::wrap(line, max_width).join("\n"), opacity)
+        })
+        .collect();
+    if let Some(draft) = chat.draft() {
+        // Garder la fin du message visible pendant la saisie
+        let text: Vec<char> = format!("> {}_", draft).chars().collect();
+        let columns = (max_width + 1) / font::ADVANCE;
+        let start = text.len().saturating_sub(columns);
+        blocks.push((text[start..].iter().collect(), 1.0));
+    }
+
+    let mut bottom = screen.height as i32 - MARGIN as i32;
+    for (text, opacity) in blocks.iter().rev() {
+        let (_, height) = font::text_size(text);
+        let top = bottom - height as i32 - font::PADDING as i32;
+        if top < 0 {
+            break;
+        }
+        let style = TextStyle {
+            background: Some((font::BLACK, 0.5)),
+            opacity: *opacity,
+            ..TextStyle::plain(font::WHITE)
+        };
+        font::draw_text(screen, text, Point { x: inset, y: top }, &style);
+        bottom = top - font::PADDING as i32 - 1;
+    }
+}
+
+fn server_address(args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
     // Récupération des arguments
     let server_name =
         args.get(3).ok_or("Server name not provided in arguments")?;
     let port = args.get(4).ok_or("Port not provided in arguments")?;
-    let server_address = format!("{}:{}", server_name, port);
+    Ok(format!("{}:{}", server_name, port))
+}
+
+// Pseudonyme donné après le port ou, à défaut, demandé dans le terminal ;
+// une réponse vide permet de jouer sans pseudonyme
+fn nickname(
+    args: &[&str],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if let Some(name) = args.get(5) {
+        check_name(name)?;
+        return Ok(Some(name.to_string()));
+    }
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(None);
+    }
+    loop {
+        print!("Pseudonyme (Entrée pour aucun) : ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let name = line.trim();
+        if name.is_empty() {
+            return Ok(None);
+        }
+        match check_name(name) {
+            Ok(()) => return Ok(Some(name.to_string())),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
 
-    // Connexion au serveur
+fn init_server(
+    server_address: &str,
+) -> Result<(TcpStream, TcpStream), Box<dyn std::error::Error>> {
+    // Connexion au serveur, en essayant chaque adresse résolue
     println!("Connecting to server at {}", server_address);
-    let stream = TcpStream::connect(&server_address).map_err(|e| {
+    let mut result = Err("Adresse du serveur introuvable".into());
+    for address in server_address.to_socket_addrs()? {
+        result = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+            .map_err(|e| e.into());
+        if result.is_ok() {
+            break;
+        }
+    }
+    let stream: TcpStream = result.map_err(|e: Box<dyn std::error::Error>| {
         eprintln!("Failed to connect to server: {}", e);
         e
     })?;
@@ -426,146 +939,569 @@ fn init_server(
     Ok((output, input))
 }
 
-fn read_lines_nonblocking(
-    input: &mut BufReader<TcpStream>
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+// Flux d'écriture et de lecture, décodeur et réponse du serveur
+type Connection = (TcpStream, TcpStream, FrameDecoder, Welcome);
+
+// Connexion et poignée de main ; `resume` présente le jeton d'une session
+// précédente et `name` notre pseudonyme. Le décodeur retourné suit le format
+// négocié
+fn connect(
+    server_address: &str,
+    resume: Option<String>,
+    name: Option<&str>,
+) -> Result<Connection, Box<dyn std::error::Error>> {
+    let (mut output, mut input) = init_server(server_address)?;
+    let mut decoder = FrameDecoder::new(Framing::Lines);
+    input.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let welcome =
+        handshake(&mut output, &mut input, &mut decoder, resume, name)?;
+    input.set_read_timeout(None)?;
+    decoder.set_framing(Framing::from_capabilities(&welcome.capabilities));
+    Ok((output, input, decoder, welcome))
+}
+
+fn handshake(
+    output: &mut TcpStream,
+    input: &mut TcpStream,
+    decoder: &mut FrameDecoder,
+    resume: Option<String>,
+    name: Option<&str>,
+) -> Result<Welcome, Box<dyn std::error::Error>> {
+    // GAME_CLIENT_UDP=0 garde les positions sur la connexion TCP
+    let mut capabilities = vec![BINARY_FRAMES.to_string()];
+    if std::env::var("GAME_CLIENT_UDP").map_or(true, |v| v != "0") {
+        capabilities.push(UDP_CHANNEL.to_string());
+    }
+    let hello = ClientMessage::Hello(Hello {
+        version: PROTOCOL_VERSION,
+        capabilities,
+        resume,
+        name: name.map(str::to_string),
+    });
+    output.write_all(&hello.encode(Framing::Lines)?)?;
+    output.flush()?;
+
+    // Attendre la réponse du serveur (bloquant) ; les octets reçus au-delà
+    // de `welcome` restent dans le décodeur
+    let frame = decoder
+        .read_frame(input)?
+        .ok_or("Connexion fermée pendant la poignée de main")?;
+
+    match ServerMessage::decode(&frame)? {
+        ServerMessage::Welcome(welcome) => Ok(welcome),
+        ServerMessage::Rejected(reason) => {
+            Err(format!("Connexion refusée par le serveur : {}", reason).into())
+        }
+        _ => Err(format!("Réponse inattendue à 'hello' : '{}'", frame.verb)
+            .into()),
+    }
+}
+
+// Retourne les trames disponibles et indique si la connexion a été fermée
+fn read_frames_nonblocking(
+    input: &mut TcpStream,
+    decoder: &mut FrameDecoder,
+) -> Result<(Vec<Frame>, bool), Box<dyn std::error::Error>> {
     fn inner(
-        input: &mut BufReader<TcpStream>
-    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let mut lines = Vec::new();
+        input: &mut TcpStream,
+        decoder: &mut FrameDecoder,
+    ) -> Result<(Vec<Frame>, bool), Box<dyn std::error::Error>> {
+        let mut frames = Vec::new();
         loop {
-            let mut line = String::new();
-            match input.read_line(&mut line) {
-                Ok(r) => {
-                    if !line.is_empty() {
-                        lines.push(line);
-                    }
-                    if r == 0 {
-                        lines.push(String::new());
-                        break;
-                    }
-                }
+            match decoder.read_frame(input) {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => return Ok((frames, true)),
                 Err(e) => {
                     if e.kind() != ErrorKind::WouldBlock {
                         Err(e)?
                     }
-                    if line.is_empty() {
-                        break;
-                    }
+                    return Ok((frames, false));
                 }
             }
         }
-        Ok(lines)
     }
-    input.get_mut().set_nonblocking(true)?;
-    let result = inner(input);
-    input.get_mut().set_nonblocking(false)?;
+    input.set_nonblocking(true)?;
+    let result = inner(input, decoder);
+    input.set_nonblocking(false)?;
     result
 }
 
+// « nom (L×H, N joueurs) »
+fn describe_room(room: &RoomInfo) -> String {
+    format!(
+        "{} ({}×{}, {} joueur{})",
+        room.name,
+        room.width,
+        room.height,
+        room.players,
+        if room.players > 1 { "s" } else { "" }
+    )
+}
+
+// Le serveur confirme notre salon : changer de monde si besoin. Notre
+// personnage y apparaît ailleurs et l'état complet du salon arrive avec le
+// prochain instantané
+fn enter_room(app: &mut Application, room: RoomInfo) {
+    println!("Salon {}", describe_room(&room));
+    app.chat.push(format!("Salon {}", describe_room(&room)));
+    if app.room.as_ref() != Some(&room.name) {
+        app.world_width = room.width;
+        app.world_height = room.height;
+        app.room = Some(room.name);
+        app.predicted = None;
+        app.pending_inputs.clear();
+    }
+    app.redraw();
+}
+
+fn apply_snapshot(
+    app: &mut Application,
+    snapshot: Snapshot,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let latest = app.snapshots.back().map(|(seq, _)| *seq);
+    if latest.is_some_and(|latest| snapshot.seq <= latest) {
+        return Ok(()); // instantané dépassé
+    }
+
+    // Un delta s'applique à l'état de référence qu'il désigne, pas
+    // forcément au dernier état reçu
+    let mut clients = match snapshot.base {
+        None => HashMap::new(),
+        Some(base) => {
+            match app.snapshots.iter().find(|(seq, _)| *seq == base) {
+                Some((_, clients)) => clients.clone(),
+                None => {
+                    eprintln!(
+                        "Instantané {} ignoré : référence {} inconnue",
+                        snapshot.seq, base
+                    );
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    for id in &snapshot.left {
+        if clients.remove(id).is_some() {
+            println!("Client supprimé : id={}", id);
+        }
+    }
+    for player in snapshot.players {
+        match (clients.get_mut(&player.id), player.image, player.position) {
+            (Some(client), image, position) => {
+                if let Some(image) = image {
+                    client.image = image;
+                }
+                if let Some(name) = player.name {
+                    client.name = Some(name);
+                }
+                if let Some(position) = position {
+                    client.position = position;
+                }
+            }
+            (None, Some(image), Some(position)) => {
+                println!(
+                    "Nouveau client ajouté : id={}, position={:?}",
+                    player.id, position
+                );
+                let name = player.name;
+                clients.insert(player.id, ClientInfo { position, image, name });
+            }
+            (None, _, _) => {
+                eprintln!("Client inconnu dans l'instantané : id={}", player.id);
+            }
+        }
+    }
+
+    // Demander les avatars manquants
+    for client in clients.values() {
+        app.images
+            .ensure(&client.image, app.output.as_mut(), app.framing)?;
+    }
+
+    if app.udp.as_ref().is_some_and(|udp| udp.is_ready()) {
+        // Les positions arrivent par UDP, plus récentes que celles de l'état
+        // de référence
+        for (id, client) in clients.iter_mut() {
+            if let Some(current) = app.clients.get(id) {
+                client.position = current.position;
+            }
+        }
+    } else {
+        let others = clients
+            .iter()
+            .filter(|(id, _)| **id != app.client_id)
+            .map(|(id, client)| (*id, client.position))
+            .collect();
+        app.interpolator.record(snapshot.time, &others);
+    }
+
+    app.clients = clients.clone();
+    app.snapshots.push_back((snapshot.seq, clients));
+    while app.snapshots.len() > SNAPSHOT_HISTORY {
+        app.snapshots.pop_front();
+    }
+    reconcile(app, snapshot.last_input);
+    app.redraw();
+
+    // Acquitter l'instantané pour que les prochains deltas s'appuient dessus
+    if let Some(output) = app.output.as_mut() {
+        output.write_all(&ClientMessage::Ack(snapshot.seq).encode(app.framing)?)?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+// Repartir de la position faisant autorité et rejouer les déplacements que
+// le serveur n'a pas encore traités
+fn reconcile(
+    app: &mut Application,
+    last_input: Option<u32>,
+) {
+    if let Some(last_input) = last_input {
+        app.pending_inputs.retain(|motion| motion.seq > last_input);
+    }
+    let Some(me) = app.clients.get(&app.client_id) else {
+        app.predicted = None;
+        return;
+    };
+    let max = app.max_position();
+    let predicted = app.pending_inputs.iter().fold(me.position, |p, motion| {
+        apply_motion(p, motion.delta, max)
+    });
+    if let Some(previous) = app.predicted.filter(|p| *p != predicted) {
+        println!(
+            "Réconciliation : position anticipée {:?} corrigée en {:?}",
+            previous, predicted
+        );
+    }
+    app.predicted = Some(predicted);
+}
+
+// Positions reçues par UDP : elles font autorité comme celles des instantanés
+fn apply_positions(
+    app: &mut Application,
+    positions: Positions,
+) {
+    for (id, position) in &positions.players {
+        if let Some(client) = app.clients.get_mut(id) {
+            client.position = *position;
+        }
+    }
+    let others = positions
+        .players
+        .iter()
+        .filter(|(id, _)| *id != app.client_id)
+        .copied()
+        .collect();
+    app.interpolator.record(positions.time, &others);
+    reconcile(app, positions.last_input);
+    app.redraw();
+}
+
+// Connexion perdue : tenter de reprendre la session si le serveur en a
+// proposé une, sinon quitter après l'avoir signalé
+fn connection_lost(app: &mut Application, reason: &str) {
+    eprintln!("Connexion perdue : {}", reason);
+    app.output = None;
+    app.input = None;
+    app.udp = None;
+    match &app.session {
+        Some(session) => {
+            println!(
+                "Reprise de la session possible pendant {} s",
+                session.grace_ms / 1000
+            );
+            app.lost = Some(Instant::now());
+            app.last_attempt = None;
+        }
+        None => close(app, Closing::Lost(reason.to_string())),
+    }
+}
+
+// Fin de partie : plus rien n'est envoyé ni reçu, la raison reste affichée
+// pendant `CLOSING_NOTICE` avant de quitter
+fn close(app: &mut Application, closing: Closing) {
+    app.output = None;
+    app.input = None;
+    app.udp = None;
+    app.lost = None;
+    app.attempt = None;
+    app.closing = Some((closing, Instant::now()));
+    app.redraw();
+}
+
+// Se reconnecter en présentant le jeton de session, à intervalles réguliers
+// jusqu'à la fin du délai de grâce. La connexion et la poignée de main se
+// font dans un thread pour ne pas bloquer l'affichage : chaque appel relève
+// le résultat de la tentative en cours
+fn try_resume(app: &mut Application) {
+    let (Some(session), Some(lost)) = (app.session.clone(), app.lost) else {
+        return;
+    };
+    if lost.elapsed() > Duration::from_millis(session.grace_ms) {
+        eprintln!("Session expirée, abandon");
+        let reason = "reprise de la session impossible".to_string();
+        close(app, Closing::Lost(reason));
+        return;
+    }
+    if let Some(attempt) = &app.attempt {
+        let result = match attempt.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                Err("tentative interrompue".to_string())
+            }
+        };
+        app.attempt = None;
+        match result {
+            Ok((output, input, decoder, welcome)) => {
+                if let Err(e) = resume(app, output, input, decoder, welcome) {
+                    connection_lost(app, &e.to_string());
+                }
+            }
+            Err(e) => eprintln!("Reprise de la session impossible : {}", e),
+        }
+        return;
+    }
+    if app
+        .last_attempt
+        .is_some_and(|attempt| attempt.elapsed() < RESUME_INTERVAL)
+    {
+        return;
+    }
+    app.last_attempt = Some(Instant::now());
+
+    let (sender, receiver) = mpsc::channel();
+    let server = app.server.clone();
+    let name = app.name.clone();
+    std::thread::spawn(move || {
+        let result = connect(&server, Some(session.token), name.as_deref())
+            .map_err(|e| e.to_string());
+        // Application déjà fermée : le résultat n'intéresse plus personne
+        let _ = sender.send(result);
+    });
+    app.attempt = Some(receiver);
+}
+
+// Repartir sur la nouvelle connexion ; le serveur renvoie l'état complet du
+// monde et les déplacements sont renumérotés
+fn resume(
+    app: &mut Application,
+    mut output: TcpStream,
+    input: TcpStream,
+    decoder: FrameDecoder,
+    welcome: Welcome,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let framing = Framing::from_capabilities(&welcome.capabilities);
+    if welcome.resumed {
+        println!("Session reprise : client {}", welcome.client_id);
+    } else {
+        // Session expirée côté serveur : rejoindre comme nouveau joueur
+        println!("Session expirée : nouveau client {}", welcome.client_id);
+        let avatar = app.avatar.as_ref().and_then(|hash| app.images.get(hash));
+        if let Some(image) = avatar {
+            let message = ClientMessage::Image(image.clone());
+            output.write_all(&message.encode(framing)?)?;
+            output.flush()?;
+        }
+    }
+
+    app.udp = open_udp(&input, &welcome);
+    app.client_id = welcome.client_id;
+    app.world_width = welcome.world_width;
+    app.world_height = welcome.world_height;
+    app.room = welcome.room;
+    app.framing = framing;
+    app.decoder = decoder;
+    app.output = Some(output);
+    app.input = Some(input);
+    app.heartbeat = welcome.heartbeat.map(Heartbeat::new);
+    app.session = welcome.session;
+    app.lost = None;
+    app.last_attempt = None;
+    app.snapshots.clear();
+    app.next_input = 0;
+    app.pending_inputs.clear();
+    app.predicted = None;
+    app.interpolator = Interpolator::new();
+    Ok(())
+}
+
+// Départ volontaire : le serveur n'a pas à conserver la session
+fn leave(app: &mut Application) {
+    let _ = send_message(app, &ClientMessage::Leave);
+}
+
+fn send_message(
+    app: &mut Application,
+    message: &ClientMessage,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(output) = app.output.as_mut() {
+        output.write_all(&message.encode(app.framing)?)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
 fn handle_messages(
     app: &mut Application
 ) -> Result<(), Box<dyn std::error::Error>> {
     match app.input.take() {
-        Some(stream) => {
-            // Créer un BufReader à partir du TcpStream
-            let mut reader = std::io::BufReader::new(stream.try_clone()?);
-
-            let lines = read_lines_nonblocking(&mut reader).map_err(|e| {
-                format!("Erreur de lecture des lignes : {}", e)
-            })?;
+        Some(mut stream) => {
+            let (frames, closed) =
+                read_frames_nonblocking(&mut stream, &mut app.decoder)
+                    .map_err(|e| {
+                        format!("Erreur de lecture des messages : {}", e)
+                    })?;
 
-            for line in lines {
-                if line.trim().is_empty() {
-                    app.status = UpdateStatus::Quit;
-                    return Ok(());
+            if let Some(heartbeat) = app.heartbeat.as_mut() {
+                if !frames.is_empty() {
+                    heartbeat.received();
                 }
+            }
 
-                if let Some(data) = line.strip_prefix("image ") {
-                    match serde_json::from_str::<(u32, Image, Point)>(
-                        data.trim(),
-                    ) {
-                        Ok((id, image, position)) => {
-                            app.clients
-                                .insert(id, ClientInfo { position, image });
-                            println!("Nouveau client ajouté : id={}, position={:?}", id, position);
-                            app.status = UpdateStatus::Redraw;
-                        }
-                        Err(e) => {
-                            eprintln!("Erreur de désérialisation JSON pour image : {}", e);
-                        }
+            for frame in frames {
+                match ServerMessage::decode(&frame) {
+                    Ok(ServerMessage::Snapshot(snapshot)) => {
+                        apply_snapshot(app, snapshot)?;
                     }
-                } else if let Some(data) = line.strip_prefix("client_left ") {
-                    match data.trim().parse::<u32>() {
-                        Ok(id) => {
-                            if app.clients.remove(&id).is_some() {
-                                println!("Client supprimé : id={}", id);
-                                app.status = UpdateStatus::Redraw;
-                            } else {
-                                println!(
-                                    "Client inconnu à supprimer : id={}",
-                                    id
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Erreur de parsing de l'identifiant client_left : {}", e);
+                    Ok(ServerMessage::ImageData { hash, image }) => {
+                        // Vérifier que le contenu correspond bien à l'empreinte
+                        if !image.is_consistent() {
+                            eprintln!(
+                                "Image {} reçue incohérente ({}×{}, {} pixels)",
+                                hash,
+                                image.width,
+                                image.height,
+                                image.pixels.len()
+                            );
+                        } else if image.hash() == hash {
+                            app.images.insert(image);
+                            println!("Image {} reçue", hash);
+                            app.redraw();
+                        } else {
+                            eprintln!(
+                                "Image reçue ne correspondant pas à l'empreinte {}",
+                                hash
+                            );
                         }
                     }
-                } else if let Some(data) = line.strip_prefix("position ") {
-                    match serde_json::from_str::<(u32, Point)>(data) {
-                        Ok((id, new_position)) => {
-                            if let Some(client) = app.clients.get_mut(&id) {
-                                client.position = new_position;
-                                println!(
-                                    "Position mise à jour pour le client id={}: {:?}",
-                                    id, new_position
-                                );
-                                app.status = UpdateStatus::Redraw;
-                            } else {
-                                println!("Client inconnu pour mise à jour de position : id={}", id);
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Erreur de désérialisation JSON pour position_update : {}", e);
-                        }
+                    Ok(ServerMessage::Welcome(_) | ServerMessage::Rejected(_)) => {
+                        eprintln!(
+                            "Message de poignée de main inattendu : {}",
+                            frame.verb
+                        );
                     }
-                }
-                if let Some(data) = line.strip_prefix("all_clients") {
-                    match serde_json::from_str::<Vec<(u32, Image, Point)>>(
-                        data,
-                    ) {
-                        Ok(client_list) => {
-                            for (id, image, position) in client_list {
-                                app.clients.insert(
-                                    id,
-                                    ClientInfo { position, image },
-                                );
-                                println!(
-                                    "Nouveau client ajouté : id={}, position={:?}",
-                                    id, position
-                                );
+                    Ok(ServerMessage::Kicked(reason)) => {
+                        eprintln!("Déconnecté par le serveur : {}", reason);
+                        close(app, Closing::Kicked(reason));
+                        return Ok(());
+                    }
+                    Ok(ServerMessage::ServerShutdown(notice)) => {
+                        // Arrêt annoncé : inutile de tenter une reprise,
+                        // l'avis reste affiché quelques secondes
+                        match notice.restart_in {
+                            Some(seconds) => println!(
+                                "Arrêt du serveur : {} (redémarrage prévu \
+                                 dans {} s)",
+                                notice.reason, seconds
+                            ),
+                            None => {
+                                println!("Arrêt du serveur : {}", notice.reason)
                             }
-                            app.status = UpdateStatus::Redraw;
-                        }
-                        Err(e) => {
-                            eprintln!(
-                                "Erreur de désérialisation JSON pour all_clients : {}",
-                                e
-                            );
                         }
+                        close(app, Closing::Shutdown(notice));
+                        return Ok(());
+                    }
+                    Ok(ServerMessage::Positions(_)) => {
+                        eprintln!("Positions reçues par TCP, ignorées");
+                    }
+                    Ok(ServerMessage::Ping(seq)) => {
+                        send_message(app, &ClientMessage::Pong(seq))?;
+                    }
+                    Ok(ServerMessage::Pong(_)) => {}
+                    Ok(ServerMessage::Error(reply)) => {
+                        eprintln!(
+                            "Message '{}' refusé par le serveur ({}) : {}",
+                            reply.verb, reply.code, reply.message
+                        );
+                        let refused = match reply.verb.as_str() {
+                            "chat" => "Message refusé",
+                            "create_room" | "join_room" => "Salon refusé",
+                            _ => continue,
+                        };
+                        app.chat
+                            .push(format!("{} : {}", refused, reply.message));
+                        app.redraw();
+                    }
+                    Ok(ServerMessage::Room(room)) => enter_room(app, room),
+                    Ok(ServerMessage::Rooms(rooms)) => {
+                        let rooms: Vec<String> =
+                            rooms.iter().map(describe_room).collect();
+                        let rooms = format!("Salons : {}", rooms.join(", "));
+                        println!("{}", rooms);
+                        app.chat.push(rooms);
+                        app.redraw();
+                    }
+                    Ok(ServerMessage::Chat(line)) => {
+                        let author = match line.name {
+                            Some(name) => name,
+                            None => format!("Joueur {}", line.from),
+                        };
+                        println!("{} : {}", author, line.text);
+                        app.chat.push(format!("{} : {}", author, line.text));
+                        app.redraw();
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Message du serveur mal formaté '{}' : {}",
+                            frame.verb, e
+                        );
                     }
-                } else {
-                    println!("Message reçu : {}", line);
                 }
             }
 
+            if closed {
+                return Err("connexion fermée par le serveur".into());
+            }
+
             app.input = Some(stream);
+
+            // Connexion à demi ouverte ou serveur figé : ne pas attendre
+            // indéfiniment
+            if let Some(heartbeat) = app.heartbeat.as_mut() {
+                if let Some(silent) = heartbeat.lost() {
+                    return Err(format!(
+                        "aucun message du serveur depuis {:.1} s",
+                        silent.as_secs_f64()
+                    )
+                    .into());
+                }
+                if let Some(seq) = heartbeat.next_ping() {
+                    send_message(app, &ClientMessage::Ping(seq))?;
+                }
+            }
         }
         None => {
             return Err("Aucun TcpStream valide dans app.input".into());
         }
     }
 
+    // Canal UDP : une erreur n'interrompt pas la partie, TCP reste utilisable
+    let received = match app.udp.as_mut() {
+        Some(udp) => {
+            udp.greet().and_then(|_| udp.receive()).unwrap_or_else(|e| {
+                eprintln!("Erreur sur le canal UDP : {}", e);
+                Vec::new()
+            })
+        }
+        None => Vec::new(),
+    };
+    for positions in received {
+        apply_positions(app, positions);
+    }
+
     Ok(())
 }
@@ -0,0 +1,112 @@
+use game_protocol::{
+    ClientMessage, Frame, Framing, Positions, ServerMessage, UdpHello, UdpOffer,
+};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Intervalle entre deux `udp_hello` tant que le serveur n'a pas répondu
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+
+// Taille maximale d'un datagramme UDP
+const MAX_DATAGRAM: usize = 65_536;
+
+// Canal UDP négocié dans `welcome` : les déplacements y partent et les
+// positions y arrivent une fois que le serveur a reçu `udp_hello`
+#[derive(Debug)]
+pub struct UdpChannel {
+    socket: UdpSocket,
+    hello: UdpHello,
+    last_hello: Option<Instant>,
+    last_seq: Option<u64>, // Numéro des dernières positions reçues
+}
+
+impl UdpChannel {
+    pub fn open(
+        server: SocketAddr,
+        offer: UdpOffer,
+        client_id: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let local: SocketAddr = if server.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect((server.ip(), offer.port))?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpChannel {
+            socket,
+            hello: UdpHello {
+                client_id,
+                token: offer.token,
+            },
+            last_hello: None,
+            last_seq: None,
+        })
+    }
+
+    // Le serveur connaît notre adresse dès qu'il nous a envoyé des positions
+    pub fn is_ready(&self) -> bool {
+        self.last_seq.is_some()
+    }
+
+    pub fn send(
+        &self,
+        message: &ClientMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.socket.send(&message.encode(Framing::Binary)?)?;
+        Ok(())
+    }
+
+    // Se présenter au serveur, en répétant tant qu'il n'a pas répondu
+    pub fn greet(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.is_ready()
+            || self
+                .last_hello
+                .is_some_and(|sent| sent.elapsed() < HELLO_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.send(&ClientMessage::UdpHello(self.hello))?;
+        self.last_hello = Some(Instant::now());
+        Ok(())
+    }
+
+    // Positions reçues depuis le dernier appel, dans l'ordre ; celles qui
+    // arrivent après des positions plus récentes sont écartées
+    pub fn receive(
+        &mut self,
+    ) -> Result<Vec<Positions>, Box<dyn std::error::Error>> {
+        let mut received = Vec::new();
+        let mut buffer = vec![0u8; MAX_DATAGRAM];
+        loop {
+            let len = match self.socket.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Port du serveur injoignable (ICMP) : réessayer plus tard
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => break,
+                Err(e) => return Err(e.into()),
+            };
+            let message = Frame::from_datagram(&buffer[..len])
+                .map_err(|e| e.into())
+                .and_then(|frame| ServerMessage::decode(&frame));
+            match message {
+                Ok(ServerMessage::Positions(positions)) => {
+                    if self.last_seq.is_some_and(|seq| positions.seq <= seq) {
+                        println!(
+                            "Positions {} arrivées en retard, ignorées",
+                            positions.seq
+                        );
+                        continue;
+                    }
+                    self.last_seq = Some(positions.seq);
+                    received.push(positions);
+                }
+                Ok(_) => eprintln!("Message inattendu par UDP"),
+                Err(e) => eprintln!("Datagramme mal formaté : {}", e),
+            }
+        }
+        Ok(received)
+    }
+}
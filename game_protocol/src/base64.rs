@@ -0,0 +1,96 @@
+// Base64 standard (RFC 4648), utilisé pour les images et la poignée de
+// main WebSocket
+
+const ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let idx = (n >> (18 - 6 * i)) & 0x3f;
+                text.push(ALPHABET[idx as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return Err("Base64 tronqué".to_string());
+        }
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = ALPHABET
+                .iter()
+                .position(|a| a == c)
+                .ok_or_else(|| {
+                    format!("Caractère base64 invalide : {:?}", *c as char)
+                })?;
+            n |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exemples de la RFC 4648, section 10
+    const VECTORS: &[(&str, &str)] = &[
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn rfc_vectors() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(encode(plain.as_bytes()), *encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn padding_is_optional_when_decoding() {
+        assert_eq!(decode("Zg").unwrap(), b"f");
+        assert_eq!(decode("Zm8").unwrap(), b"fo");
+    }
+
+    #[test]
+    fn all_byte_values_roundtrip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in 0..bytes.len() {
+            assert_eq!(decode(&encode(&bytes[..len])).unwrap(), &bytes[..len]);
+        }
+    }
+
+    #[test]
+    fn invalid_input_is_refused() {
+        assert!(decode("Z").is_err());
+        assert!(decode("Zm9vY").is_err());
+        assert!(decode("Zm9v!A==").is_err());
+        assert!(decode("Zm 9v").is_err());
+    }
+}
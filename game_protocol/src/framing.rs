@@ -0,0 +1,371 @@
+use std::io::{Error, ErrorKind, Read};
+
+// Fonctionnalité annoncée dans `hello` pour utiliser les trames binaires
+pub const BINARY_FRAMES: &str = "binary_frames";
+
+// Fonctionnalité annoncée dans `hello` pour recevoir les positions et
+// envoyer les déplacements par UDP (une trame binaire par datagramme)
+pub const UDP_CHANNEL: &str = "udp";
+
+// Taille maximale acceptée pour le contenu d'une trame (protection mémoire)
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+// Verbes connus ; l'indice dans ce tableau sert d'étiquette aux trames
+// binaires, il ne faut donc qu'ajouter de nouveaux verbes à la fin
+pub(crate) const VERBS: &[&str] = &[
+    "hello",
+    "welcome",
+    "rejected",
+    "motion",
+    "image",
+    "get_image",
+    "image_data",
+    "snapshot",
+    "ack",
+    "udp_hello",
+    "positions",
+    "kicked",
+    "ping",
+    "pong",
+    "leave",
+    "server_shutdown",
+    "error",
+    "chat",
+    "list_rooms",
+    "rooms",
+    "create_room",
+    "join_room",
+    "leave_room",
+    "room",
+];
+
+// Taille de l'en-tête d'une trame binaire : étiquette (1) + longueur (4)
+const HEADER_LEN: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    // Une ligne de texte par message : `verbe contenu\n` (lisible avec netcat)
+    Lines,
+    // Étiquette du verbe (u8) + longueur (u32 big-endian) + contenu brut
+    Binary,
+}
+
+impl Framing {
+    // Format retenu à partir des fonctionnalités négociées dans `welcome`
+    pub fn from_capabilities(capabilities: &[String]) -> Self {
+        if capabilities.iter().any(|c| c == BINARY_FRAMES) {
+            Framing::Binary
+        } else {
+            Framing::Lines
+        }
+    }
+
+    pub fn frame(self, verb: &str, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Framing::Lines => {
+                if payload.contains(&b'\n') {
+                    return Err(invalid_data(format!(
+                        "Contenu multiligne pour '{}'",
+                        verb
+                    )));
+                }
+                let mut bytes = Vec::with_capacity(verb.len() + payload.len() + 2);
+                bytes.extend_from_slice(verb.as_bytes());
+                bytes.push(b' ');
+                bytes.extend_from_slice(payload);
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+            Framing::Binary => {
+                let tag = VERBS
+                    .iter()
+                    .position(|v| *v == verb)
+                    .ok_or_else(|| {
+                        invalid_data(format!("Verbe sans étiquette : '{}'", verb))
+                    })?;
+                if payload.len() > MAX_FRAME_LEN {
+                    return Err(invalid_data(format!(
+                        "Trame '{}' trop longue ({} octets)",
+                        verb,
+                        payload.len()
+                    )));
+                }
+                let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+                bytes.push(tag as u8);
+                bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(payload);
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+// Message reçu, découpé en verbe et contenu mais pas encore interprété
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub verb: String,
+    pub payload: Vec<u8>,
+    pub framing: Framing, // Format d'origine, dont dépend celui des images
+}
+
+impl Frame {
+    // Trame binaire occupant exactement un datagramme UDP
+    pub fn from_datagram(datagram: &[u8]) -> Result<Frame, Error> {
+        let mut decoder = FrameDecoder::new(Framing::Binary);
+        decoder.extend(datagram);
+        match decoder.next_frame()? {
+            Some(frame) if decoder.buffer.is_empty() => Ok(frame),
+            _ => Err(invalid_data(format!(
+                "Datagramme invalide ({} octets)",
+                datagram.len()
+            ))),
+        }
+    }
+
+    pub fn payload_str(&self) -> Result<&str, Error> {
+        std::str::from_utf8(&self.payload).map_err(|e| invalid_data(e.to_string()))
+    }
+}
+
+// Accumule les octets reçus et en extrait les trames complètes ; le format
+// peut changer en cours de route (après la poignée de main) sans perdre les
+// octets déjà lus
+#[derive(Debug)]
+pub struct FrameDecoder {
+    framing: Framing,
+    buffer: Vec<u8>,
+    scanned: usize, // Octets déjà parcourus sans trouver de fin de ligne
+}
+
+impl FrameDecoder {
+    pub fn new(framing: Framing) -> Self {
+        FrameDecoder {
+            framing,
+            buffer: Vec::new(),
+            scanned: 0,
+        }
+    }
+
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+        self.scanned = 0;
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Octets reçus qui ne forment pas encore une trame complète
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        match self.framing {
+            Framing::Lines => loop {
+                // Ne parcourir que les octets arrivés depuis le dernier appel
+                let unscanned = &self.buffer[self.scanned..];
+                let end = match unscanned.iter().position(|b| *b == b'\n') {
+                    Some(end) => self.scanned + end,
+                    // Ne pas accumuler indéfiniment une ligne sans fin
+                    None if self.buffer.len() > MAX_FRAME_LEN => {
+                        return Err(invalid_data(format!(
+                            "Ligne trop longue (plus de {} octets)",
+                            MAX_FRAME_LEN
+                        )));
+                    }
+                    None => {
+                        self.scanned = self.buffer.len();
+                        return Ok(None);
+                    }
+                };
+                self.scanned = 0;
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = std::str::from_utf8(&line)
+                    .map_err(|e| invalid_data(e.to_string()))?
+                    .trim();
+                if line.is_empty() {
+                    continue; // ignorer les lignes vides
+                }
+                let (verb, payload) = match line.split_once(' ') {
+                    Some((verb, payload)) => (verb, payload.trim()),
+                    None => (line, ""),
+                };
+                return Ok(Some(Frame {
+                    verb: verb.to_string(),
+                    payload: payload.as_bytes().to_vec(),
+                    framing: Framing::Lines,
+                }));
+            },
+            Framing::Binary => {
+                if self.buffer.len() < HEADER_LEN {
+                    return Ok(None);
+                }
+                let tag = self.buffer[0] as usize;
+                let verb = VERBS.get(tag).ok_or_else(|| {
+                    invalid_data(format!("Étiquette de trame inconnue : {}", tag))
+                })?;
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&self.buffer[1..HEADER_LEN]);
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(invalid_data(format!(
+                        "Trame '{}' trop longue ({} octets)",
+                        verb, len
+                    )));
+                }
+                if self.buffer.len() < HEADER_LEN + len {
+                    return Ok(None);
+                }
+                let payload =
+                    self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+                self.buffer.drain(..HEADER_LEN + len);
+                Ok(Some(Frame {
+                    verb: verb.to_string(),
+                    payload,
+                    framing: Framing::Binary,
+                }))
+            }
+        }
+    }
+
+    // Lit depuis `input` jusqu'à obtenir une trame complète ; `None` signale
+    // la fin de la connexion (une erreur `WouldBlock` laisse l'état intact)
+    pub fn read_frame(
+        &mut self,
+        input: &mut impl Read,
+    ) -> Result<Option<Frame>, Error> {
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(Some(frame));
+            }
+            let mut chunk = [0u8; 4096];
+            let r = input.read(&mut chunk)?;
+            if r == 0 {
+                return Ok(None);
+            }
+            self.extend(&chunk[..r]);
+        }
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(framing: Framing, bytes: &[u8]) -> Vec<Frame> {
+        let mut decoder = FrameDecoder::new(framing);
+        decoder.extend(bytes);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn split_frames_wait_for_their_last_byte() {
+        for framing in [Framing::Lines, Framing::Binary] {
+            let bytes = framing.frame("ack", b"42").unwrap();
+            let mut decoder = FrameDecoder::new(framing);
+            for (i, byte) in bytes.iter().enumerate() {
+                assert_eq!(decoder.next_frame().unwrap(), None);
+                decoder.extend(&[*byte]);
+                if i + 1 < bytes.len() {
+                    assert_eq!(decoder.next_frame().unwrap(), None);
+                }
+            }
+            let frame = decoder.next_frame().unwrap().unwrap();
+            assert_eq!(frame.verb, "ack");
+            assert_eq!(frame.payload, b"42");
+            assert_eq!(decoder.next_frame().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn concatenated_frames_come_out_in_order() {
+        for framing in [Framing::Lines, Framing::Binary] {
+            let mut bytes = framing.frame("ping", b"1").unwrap();
+            bytes.extend(framing.frame("leave", b"").unwrap());
+            bytes.extend(framing.frame("pong", b"2").unwrap());
+            let frames = frames(framing, &bytes);
+            let verbs: Vec<&str> =
+                frames.iter().map(|f| f.verb.as_str()).collect();
+            assert_eq!(verbs, ["ping", "leave", "pong"]);
+            assert_eq!(frames[0].payload, b"1");
+            assert!(frames[1].payload.is_empty());
+            assert_eq!(frames[2].payload, b"2");
+        }
+    }
+
+    #[test]
+    fn lines_skip_blank_lines_and_trim_payloads() {
+        let frames = frames(Framing::Lines, b"\n  \r\nack   7 \r\nleave\n");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].verb, "ack");
+        assert_eq!(frames[0].payload, b"7");
+        assert_eq!(frames[1].verb, "leave");
+    }
+
+    #[test]
+    fn framing_can_change_after_the_handshake() {
+        let mut bytes = Framing::Lines.frame("hello", b"{}").unwrap();
+        bytes.extend(Framing::Binary.frame("ack", b"3").unwrap());
+        let mut decoder = FrameDecoder::new(Framing::Lines);
+        decoder.extend(&bytes);
+        assert_eq!(decoder.next_frame().unwrap().unwrap().verb, "hello");
+        decoder.set_framing(Framing::Binary);
+        let frame = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(frame.verb, "ack");
+        assert_eq!(frame.payload, b"3");
+    }
+
+    #[test]
+    fn lines_refuse_multiline_payloads() {
+        assert!(Framing::Lines.frame("chat", b"a\nb").is_err());
+    }
+
+    #[test]
+    fn endless_line_is_refused() {
+        let mut decoder = FrameDecoder::new(Framing::Lines);
+        decoder.extend(&vec![b'a'; MAX_FRAME_LEN]);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(b"a");
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn oversized_binary_frame_is_refused() {
+        // L'en-tête suffit : le contenu annoncé n'est jamais attendu
+        let mut decoder = FrameDecoder::new(Framing::Binary);
+        decoder.extend(&[0]);
+        decoder.extend(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        assert!(decoder.next_frame().is_err());
+
+        let payload = vec![0; MAX_FRAME_LEN + 1];
+        assert!(Framing::Binary.frame("image", &payload).is_err());
+        assert!(Framing::Binary.frame("image", &payload[1..]).is_ok());
+    }
+
+    #[test]
+    fn unknown_binary_tags_and_verbs_are_refused() {
+        let mut decoder = FrameDecoder::new(Framing::Binary);
+        decoder.extend(&[VERBS.len() as u8, 0, 0, 0, 0]);
+        assert!(decoder.next_frame().is_err());
+        assert!(Framing::Binary.frame("inconnu", b"").is_err());
+    }
+
+    #[test]
+    fn datagram_holds_exactly_one_frame() {
+        let bytes = Framing::Binary.frame("ping", b"5").unwrap();
+        assert_eq!(Frame::from_datagram(&bytes).unwrap().verb, "ping");
+        assert!(Frame::from_datagram(&bytes[..bytes.len() - 1]).is_err());
+        let mut twice = bytes.clone();
+        twice.extend(&bytes);
+        assert!(Frame::from_datagram(&twice).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use crate::base64;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
+
+// Nombre maximal de pixels d'une image reçue, vérifié avant décompression
+// (les serveurs peuvent imposer des limites plus strictes)
+pub const MAX_IMAGE_PIXELS: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// Couleur des pixels transparents des avatars : ils ne sont pas dessinés et
+// ne comptent pas dans les collisions
+pub const TRANSPARENT: Color = Color { r: 0, g: 255, b: 0 };
+
+// Sur le réseau, les pixels sont compressés (voir `WireImage`)
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "WireImage")]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+// Empreinte du contenu d'une image, qui sert à l'identifier dans les caches
+// (FNV-1a 64 bits en hexadécimal, non cryptographique). Une empreinte reçue
+// a toujours ce format : elle peut servir de nom de fichier sans risque
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct ImageHash(String);
+
+// Chiffres hexadécimaux d'une empreinte
+const HASH_LEN: usize = 16;
+
+impl TryFrom<String> for ImageHash {
+    type Error = String;
+
+    fn try_from(hash: String) -> Result<Self, Self::Error> {
+        let hex = |b: &u8| b.is_ascii_digit() || (b'a'..=b'f').contains(b);
+        if hash.len() != HASH_LEN || !hash.as_bytes().iter().all(hex) {
+            return Err(format!(
+                "Empreinte d'image invalide : {:?} ({} chiffres \
+                 hexadécimaux minuscules attendus)",
+                hash, HASH_LEN
+            ));
+        }
+        Ok(ImageHash(hash))
+    }
+}
+
+impl From<ImageHash> for String {
+    fn from(hash: ImageHash) -> Self {
+        hash.0
+    }
+}
+
+impl std::fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Image {
+    // Autant de pixels que les dimensions l'annoncent : l'image peut être
+    // parcourue ligne par ligne sans sortir du tableau
+    pub fn is_consistent(&self) -> bool {
+        self.width.checked_mul(self.height) == Some(self.pixels.len())
+    }
+
+    // Le pixel (x, y), supposé dans l'image, n'est pas transparent
+    pub fn is_opaque(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x] != TRANSPARENT
+    }
+
+    pub fn hash(&self) -> ImageHash {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut h = FNV_OFFSET;
+        let mut feed = |bytes: &[u8]| {
+            for b in bytes {
+                h ^= *b as u64;
+                h = h.wrapping_mul(FNV_PRIME);
+            }
+        };
+        feed(&(self.width as u64).to_le_bytes());
+        feed(&(self.height as u64).to_le_bytes());
+        for c in &self.pixels {
+            feed(&[c.r, c.g, c.b]);
+        }
+        ImageHash(format!("{:0width$x}", h, width = HASH_LEN))
+    }
+}
+
+// Représentation compacte d'une image : les pixels sont codés par plages
+// (compte sur un octet puis r, g, b) et le résultat est transmis en base64
+// dans les lignes de texte (voir `encode_binary` pour les trames binaires)
+#[derive(Serialize, Deserialize)]
+struct WireImage {
+    width: usize,
+    height: usize,
+    rle: String,
+}
+
+// Dimensions d'une image dans l'en-tête JSON d'une trame binaire
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ImageHeader {
+    width: usize,
+    height: usize,
+}
+
+impl ImageHeader {
+    // Nombre de pixels annoncé, s'il ne dépasse pas `MAX_IMAGE_PIXELS`
+    fn pixels(&self) -> Result<usize, String> {
+        self.width
+            .checked_mul(self.height)
+            .filter(|&pixels| pixels <= MAX_IMAGE_PIXELS)
+            .ok_or_else(|| {
+                format!(
+                    "Image trop grande ({}×{}, au plus {} pixels)",
+                    self.width, self.height, MAX_IMAGE_PIXELS
+                )
+            })
+    }
+}
+
+impl Image {
+    pub(crate) fn header(&self) -> ImageHeader {
+        ImageHeader {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    // Image décrite par un en-tête et ses plages de pixels brutes
+    pub(crate) fn from_runs(
+        header: ImageHeader,
+        runs: &[u8],
+    ) -> Result<Self, String> {
+        // Ne jamais décompresser plus de pixels que l'image n'en annonce
+        let max_pixels = header.pixels()?;
+        let pixels = rle_decode(runs, max_pixels)?;
+        if pixels.len() != max_pixels {
+            return Err(format!(
+                "Image incomplète : {} pixels pour {}×{}",
+                pixels.len(),
+                header.width,
+                header.height
+            ));
+        }
+        Ok(Image {
+            width: header.width,
+            height: header.height,
+            pixels,
+        })
+    }
+}
+
+// Contenu d'une trame binaire portant une image : l'en-tête JSON du message
+// (où l'image n'est représentée que par `ImageHeader`) précédé de sa
+// longueur en u32 big-endian, puis les plages de pixels sans base64
+pub(crate) fn encode_binary(
+    header: &impl Serialize,
+    image: &Image,
+) -> Result<Vec<u8>, serde_json::Error> {
+    let json = serde_json::to_vec(header)?;
+    let runs = rle_encode(&image.pixels);
+    let mut bytes = Vec::with_capacity(4 + json.len() + runs.len());
+    bytes.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&json);
+    bytes.extend_from_slice(&runs);
+    Ok(bytes)
+}
+
+// En-tête et plages de pixels d'un contenu produit par `encode_binary`
+pub(crate) fn decode_binary<T: DeserializeOwned>(
+    payload: &[u8],
+) -> Result<(T, &[u8]), Box<dyn std::error::Error>> {
+    let (len, rest) = payload
+        .split_first_chunk::<4>()
+        .ok_or("Trame d'image sans en-tête")?;
+    let len = u32::from_be_bytes(*len) as usize;
+    if len > rest.len() {
+        return Err("En-tête de trame d'image tronqué".into());
+    }
+    let (json, runs) = rest.split_at(len);
+    Ok((serde_json::from_slice(json)?, runs))
+}
+
+impl Serialize for Image {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        WireImage {
+            width: self.width,
+            height: self.height,
+            rle: base64::encode(&rle_encode(&self.pixels)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl TryFrom<WireImage> for Image {
+    type Error = String;
+
+    fn try_from(wire: WireImage) -> Result<Self, Self::Error> {
+        let header = ImageHeader {
+            width: wire.width,
+            height: wire.height,
+        };
+        // Taille vérifiée avant même de décoder le base64
+        header.pixels()?;
+        Image::from_runs(header, &base64::decode(&wire.rle)?)
+    }
+}
+
+fn rle_encode(pixels: &[Color]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut iter = pixels.iter().peekable();
+    while let Some(color) = iter.next() {
+        let mut count = 1u8;
+        while count < u8::MAX && iter.peek() == Some(&color) {
+            iter.next();
+            count += 1;
+        }
+        bytes.extend_from_slice(&[count, color.r, color.g, color.b]);
+    }
+    bytes
+}
+
+fn rle_decode(bytes: &[u8], max_pixels: usize) -> Result<Vec<Color>, String> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err("Plages de pixels tronquées".to_string());
+    }
+    let mut pixels = Vec::new();
+    for run in bytes.chunks_exact(4) {
+        let count = run[0] as usize;
+        if count == 0 || pixels.len() + count > max_pixels {
+            return Err("Plage de pixels invalide".to_string());
+        }
+        let color = Color {
+            r: run[1],
+            g: run[2],
+            b: run[3],
+        };
+        pixels.resize(pixels.len() + count, color);
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+    const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+
+    fn wire(width: usize, height: usize, runs: &[u8]) -> WireImage {
+        WireImage {
+            width,
+            height,
+            rle: base64::encode(runs),
+        }
+    }
+
+    #[test]
+    fn long_runs_are_split_at_255_pixels() {
+        let mut pixels = vec![RED; 600];
+        pixels.push(BLUE);
+        let bytes = rle_encode(&pixels);
+        let counts: Vec<u8> = bytes.chunks(4).map(|run| run[0]).collect();
+        assert_eq!(counts, [255, 255, 90, 1]);
+        assert_eq!(rle_decode(&bytes, pixels.len()).unwrap(), pixels);
+
+        let image = Image {
+            width: 601,
+            height: 1,
+            pixels,
+        };
+        let json = serde_json::to_string(&image).unwrap();
+        assert_eq!(serde_json::from_str::<Image>(&json).unwrap(), image);
+    }
+
+    #[test]
+    fn pixel_count_must_match_the_dimensions() {
+        // Trop peu de pixels
+        let short = wire(2, 2, &[3, 1, 2, 3]);
+        assert!(Image::try_from(short).is_err());
+        // Trop de pixels : refusé avant de tout décompresser
+        let long = wire(2, 2, &[3, 1, 2, 3, 2, 1, 2, 3]);
+        assert!(Image::try_from(long).is_err());
+        let exact = wire(2, 2, &[3, 1, 2, 3, 1, 1, 2, 3]);
+        assert_eq!(Image::try_from(exact).unwrap().pixels.len(), 4);
+    }
+
+    #[test]
+    fn malformed_runs_are_refused() {
+        assert!(rle_decode(&[0, 1, 2, 3], 10).is_err());
+        assert!(rle_decode(&[1, 1, 2], 10).is_err());
+        assert!(Image::try_from(WireImage {
+            width: 1,
+            height: 1,
+            rle: "!!!!".to_string(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn oversized_images_are_refused() {
+        let side = (MAX_IMAGE_PIXELS as f64).sqrt() as usize;
+        assert!(Image::try_from(wire(side + 1, side, &[])).is_err());
+        assert!(Image::try_from(wire(usize::MAX, 2, &[])).is_err());
+        // Au maximum autorisé, seule la taille annoncée compte
+        let runs: Vec<u8> = [255, 9, 9, 9]
+            .repeat(MAX_IMAGE_PIXELS / 255)
+            .into_iter()
+            .chain([(MAX_IMAGE_PIXELS % 255) as u8, 9, 9, 9])
+            .collect();
+        let image = Image::try_from(wire(MAX_IMAGE_PIXELS, 1, &runs)).unwrap();
+        assert!(image.is_consistent());
+    }
+
+    #[test]
+    fn binary_payload_carries_raw_runs() {
+        let image = Image {
+            width: 3,
+            height: 1,
+            pixels: vec![RED, RED, BLUE],
+        };
+        let bytes = encode_binary(&image.header(), &image).unwrap();
+        let json = br#"{"width":3,"height":1}"#;
+        assert_eq!(bytes[..4], (json.len() as u32).to_be_bytes());
+        assert_eq!(&bytes[4..4 + json.len()], json);
+        assert_eq!(bytes[4 + json.len()..], [2, 255, 0, 0, 1, 0, 0, 255]);
+
+        let (header, runs) = decode_binary(&bytes).unwrap();
+        assert_eq!(Image::from_runs(header, runs).unwrap(), image);
+        // En-tête absent ou plus long que le contenu
+        assert!(decode_binary::<ImageHeader>(&bytes[..3]).is_err());
+        assert!(decode_binary::<ImageHeader>(&bytes[..10]).is_err());
+        // Plages tronquées
+        let (header, runs) = decode_binary(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(Image::from_runs(header, runs).is_err());
+    }
+
+    #[test]
+    fn hash_depends_on_dimensions_and_pixels() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![RED, BLUE],
+        };
+        let hash = image.hash();
+        assert_eq!(hash.0.len(), HASH_LEN);
+        let transposed = Image {
+            width: 1,
+            height: 2,
+            ..image.clone()
+        };
+        assert_ne!(transposed.hash(), hash);
+        let swapped = Image {
+            pixels: vec![BLUE, RED],
+            ..image.clone()
+        };
+        assert_ne!(swapped.hash(), hash);
+        assert_eq!(image.clone().hash(), hash);
+    }
+
+    #[test]
+    fn received_hashes_are_checked() {
+        let hash = |json: &str| serde_json::from_str::<ImageHash>(json);
+        let image = Image {
+            width: 1,
+            height: 1,
+            pixels: vec![RED],
+        };
+        let json = serde_json::to_string(&image.hash()).unwrap();
+        assert_eq!(hash(&json).unwrap(), image.hash());
+        assert_eq!(hash("\"0123456789abcdef\"").unwrap().0, "0123456789abcdef");
+        for invalid in [
+            "\"\"",
+            "\"0123456789abcde\"",
+            "\"0123456789abcdef0\"",
+            "\"0123456789ABCDEF\"",
+            "\"../../etc/passwd\"",
+            "\"/etc/x/01234567\"",
+            "\"0123456789abcdeé\"",
+            "16",
+        ] {
+            assert!(hash(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,773 @@
+use serde::{Deserialize, Serialize};
+
+pub mod base64;
+mod framing;
+mod image;
+pub use framing::{
+    Frame, FrameDecoder, Framing, BINARY_FRAMES, MAX_FRAME_LEN, UDP_CHANNEL,
+};
+pub use image::{Color, Image, ImageHash, MAX_IMAGE_PIXELS, TRANSPARENT};
+
+// Version du protocole, vérifiée lors de la poignée de main hello/welcome
+pub const PROTOCOL_VERSION: u32 = 3;
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Types partagés entre le client et le serveur
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+// Déplacement demandé par le client, numéroté pour que le serveur puisse
+// indiquer le dernier qu'il a appliqué (voir `Snapshot::last_input`)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Motion {
+    pub seq: u32,
+    pub delta: Point,
+}
+
+// Règle de déplacement commune au serveur (qui fait autorité) et au client
+// (qui l'applique par anticipation) : la position, coin supérieur gauche du
+// sprite, reste entre l'origine et `max` (voir `max_position`), même pour
+// un déplacement démesuré
+pub fn apply_motion(position: Point, delta: Point, max: Point) -> Point {
+    Point {
+        x: position.x.saturating_add(delta.x).clamp(0, max.x),
+        y: position.y.saturating_add(delta.y).clamp(0, max.y),
+    }
+}
+
+// Position la plus à droite et la plus basse où un sprite tient entier dans
+// le monde ; un sprite plus grand que le monde reste collé à l'origine
+pub fn max_position(
+    world_width: usize,
+    world_height: usize,
+    sprite_width: usize,
+    sprite_height: usize,
+) -> Point {
+    Point {
+        x: world_width.saturating_sub(sprite_width) as i32,
+        y: world_height.saturating_sub(sprite_height) as i32,
+    }
+}
+
+// Longueur maximale d'un pseudonyme, en caractères
+pub const MAX_NAME_LEN: usize = 16;
+
+// Longueur maximale du nom d'un salon, en caractères
+pub const MAX_ROOM_NAME_LEN: usize = 24;
+
+// Règles communes aux pseudonymes : de 1 à `MAX_NAME_LEN` caractères, des
+// lettres, des chiffres, des espaces isolés et `-_.'` (ni espaces au début
+// ou à la fin, ni caractères de contrôle)
+pub fn check_name(name: &str) -> Result<(), String> {
+    check_label(name, MAX_NAME_LEN, "le pseudonyme")
+}
+
+// Mêmes règles pour le nom d'un salon, jusqu'à `MAX_ROOM_NAME_LEN`
+// caractères
+pub fn check_room_name(name: &str) -> Result<(), String> {
+    check_label(name, MAX_ROOM_NAME_LEN, "le nom du salon")
+}
+
+fn check_label(label: &str, max_len: usize, what: &str) -> Result<(), String> {
+    let len = label.chars().count();
+    if len == 0 || len > max_len {
+        return Err(format!(
+            "{} doit faire de 1 à {} caractères",
+            what, max_len
+        ));
+    }
+    if label.trim() != label || label.contains("  ") {
+        return Err(format!("espaces superflus dans {}", what));
+    }
+    match label
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || " -_.'".contains(*c)))
+    {
+        Some(c) => Err(format!("caractère '{}' interdit dans {}", c, what)),
+        None => Ok(()),
+    }
+}
+
+// Longueur maximale d'un message de discussion, en caractères
+pub const MAX_CHAT_LEN: usize = 200;
+
+// Règles communes aux messages de discussion : du texte sur une ligne, ni
+// vide ni plus long que `MAX_CHAT_LEN` caractères
+pub fn check_chat(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err("message vide".to_string());
+    }
+    let len = text.chars().count();
+    if len > MAX_CHAT_LEN {
+        return Err(format!(
+            "message de {} caractères, au plus {} autorisés",
+            len, MAX_CHAT_LEN
+        ));
+    }
+    if text.chars().any(char::is_control) {
+        return Err("caractère de contrôle dans le message".to_string());
+    }
+    Ok(())
+}
+
+// Premier message du client : version du protocole et fonctionnalités supportées
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+    // Jeton d'une session précédente (`Welcome::session`) à reprendre
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume: Option<String>,
+    // Pseudonyme affiché au-dessus de l'avatar (voir `check_name`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// Réponse du serveur à `hello` lorsque le client est accepté
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Welcome {
+    pub client_id: u32,
+    pub world_width: usize,
+    pub world_height: usize,
+    pub server_version: String,
+    pub capabilities: Vec<String>,
+    // Présent si le canal UDP a été négocié (fonctionnalité `UDP_CHANNEL`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp: Option<UdpOffer>,
+    // Absent si le serveur ne surveille pas les connexions inactives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<HeartbeatPolicy>,
+    // Absent si le serveur ne conserve pas les joueurs déconnectés
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionOffer>,
+    // Session reprise : même identifiant, même position et même avatar,
+    // l'image n'est pas à renvoyer
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resumed: bool,
+    // Salon où se trouve le joueur, dont le monde a les dimensions ci-dessus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+}
+
+// Après une coupure, le joueur reste dans le monde pendant `grace_ms`
+// millisecondes ; un client qui se reconnecte avec `token` dans
+// `Hello::resume` le retrouve sans que les autres voient de départ
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionOffer {
+    pub token: String,
+    pub grace_ms: u64,
+}
+
+// Chaque côté envoie `ping` toutes les `interval_ms` millisecondes ; après
+// `misses` intervalles sans aucun message de l'autre côté, la connexion est
+// considérée comme perdue
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatPolicy {
+    pub interval_ms: u64,
+    pub misses: u32,
+}
+
+// Port UDP du serveur et jeton que le client doit présenter dans
+// `udp_hello` pour que ses datagrammes lui soient attribués
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpOffer {
+    pub port: u16,
+    pub token: u64,
+}
+
+// Premier datagramme du client sur le canal UDP, répété jusqu'à recevoir
+// des positions par ce canal
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpHello {
+    pub client_id: u32,
+    pub token: u64,
+}
+
+// État d'un joueur dans un instantané ; dans un delta, seuls les champs
+// modifiés depuis l'instantané de référence sont présents
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerState {
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Point>,
+}
+
+// État du monde numéroté par `seq` ; sans `base` il est complet, sinon il
+// ne contient que les différences avec l'instantané `base` acquitté par le
+// client (joueurs arrivés ou modifiés, identifiants des joueurs partis)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<u64>,
+    pub time: u64, // Horloge du serveur (ms) au moment de l'instantané
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub players: Vec<PlayerState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left: Vec<u32>,
+    // Dernier déplacement du destinataire pris en compte dans cet état
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_input: Option<u32>,
+}
+
+// Positions de tous les joueurs, envoyées à chaque pas par UDP aux clients
+// qui ont ouvert ce canal ; `seq` (celui de l'instantané du même pas)
+// permet d'écarter les datagrammes arrivés en retard
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Positions {
+    pub seq: u64,
+    pub time: u64, // Horloge du serveur (ms), comme `Snapshot::time`
+    pub players: Vec<(u32, Point)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_input: Option<u32>,
+}
+
+// Annonce de l'arrêt du serveur, dernier message avant la fermeture des
+// connexions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServerShutdown {
+    pub reason: String,
+    // Délai avant le redémarrage prévu, en secondes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_in: Option<u64>,
+}
+
+// Message de discussion relayé par le serveur à tous les joueurs, y compris
+// son auteur
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatLine {
+    pub from: u32, // Identifiant de l'auteur
+    // Pseudonyme de l'auteur au moment de l'envoi, s'il en a un
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub text: String,
+}
+
+// Salon à créer ; sans dimensions, il reprend celles du salon d'accueil, qu'il
+// ne peut pas dépasser
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomRequest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<usize>,
+}
+
+// Description d'un salon : chacun a son propre monde, ses joueurs et ses
+// instantanés
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    pub players: usize, // Joueurs présents, y compris ceux en cours de reprise
+}
+
+// Raison pour laquelle le serveur n'a pas donné suite à un message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    UnknownVerb,   // Verbe inconnu du serveur
+    Malformed,     // Contenu illisible pour ce verbe
+    Unexpected,    // Message valide mais pas à ce moment de la connexion
+    UnknownImage,  // `get_image` d'une empreinte que le serveur ne connaît pas
+    InvalidMotion, // Déplacement plus grand que le pas autorisé
+    RateLimited,   // Trop de messages de ce type par seconde
+    InvalidImage,  // Avatar incohérent ou dépassant les limites du serveur
+    InvalidChat,   // Message de discussion vide, trop long ou multiligne
+    UnknownRoom,   // Salon inexistant
+    InvalidRoom,   // Salon impossible à créer (nom, dimensions, nombre)
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ErrorCode::UnknownVerb => "unknown_verb",
+            ErrorCode::Malformed => "malformed",
+            ErrorCode::Unexpected => "unexpected",
+            ErrorCode::UnknownImage => "unknown_image",
+            ErrorCode::InvalidMotion => "invalid_motion",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InvalidImage => "invalid_image",
+            ErrorCode::InvalidChat => "invalid_chat",
+            ErrorCode::UnknownRoom => "unknown_room",
+            ErrorCode::InvalidRoom => "invalid_room",
+        })
+    }
+}
+
+// Réponse à un message ignoré par le serveur : le code est destiné au
+// programme, le texte à l'utilisateur
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorReply {
+    pub code: ErrorCode,
+    pub verb: String, // Verbe du message en cause
+    pub message: String,
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Messages envoyés par le client au serveur
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    Hello(Hello),
+    Motion(Motion),
+    Image(Image),
+    GetImage(ImageHash), // Demande d'une image absente du cache du client
+    Ack(u64),            // Dernier instantané appliqué par le client
+    UdpHello(UdpHello),  // Uniquement par datagramme UDP
+    Ping(u64),           // Signe de vie, renvoyé dans `pong`
+    Pong(u64),           // Réponse au `ping` du serveur
+    Leave,               // Départ volontaire : la session n'est pas conservée
+    Chat(String),        // Message à relayer aux autres joueurs
+    ListRooms,           // Demande de la liste des salons
+    CreateRoom(RoomRequest), // Créer un salon et le rejoindre
+    JoinRoom(String),    // Rejoindre un salon existant, désigné par son nom
+    LeaveRoom,           // Revenir au salon d'accueil
+}
+
+impl ClientMessage {
+    pub fn encode(
+        &self,
+        framing: Framing,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (verb, payload) = match self {
+            // Plages de pixels brutes plutôt qu'en base64
+            ClientMessage::Image(image) if framing == Framing::Binary => {
+                let payload = image::encode_binary(&image.header(), image)?;
+                return Ok(framing.frame("image", &payload)?);
+            }
+            ClientMessage::Hello(hello) => {
+                ("hello", serde_json::to_string(hello)?)
+            }
+            ClientMessage::Motion(motion) => {
+                ("motion", serde_json::to_string(motion)?)
+            }
+            ClientMessage::Image(image) => {
+                ("image", serde_json::to_string(image)?)
+            }
+            ClientMessage::GetImage(hash) => {
+                ("get_image", serde_json::to_string(hash)?)
+            }
+            ClientMessage::Ack(seq) => ("ack", seq.to_string()),
+            ClientMessage::UdpHello(hello) => {
+                ("udp_hello", serde_json::to_string(hello)?)
+            }
+            ClientMessage::Ping(seq) => ("ping", seq.to_string()),
+            ClientMessage::Pong(seq) => ("pong", seq.to_string()),
+            ClientMessage::Leave => ("leave", String::new()),
+            ClientMessage::Chat(text) => {
+                ("chat", serde_json::to_string(text)?)
+            }
+            ClientMessage::ListRooms => ("list_rooms", String::new()),
+            ClientMessage::CreateRoom(request) => {
+                ("create_room", serde_json::to_string(request)?)
+            }
+            ClientMessage::JoinRoom(name) => {
+                ("join_room", serde_json::to_string(name)?)
+            }
+            ClientMessage::LeaveRoom => ("leave_room", String::new()),
+        };
+        Ok(framing.frame(verb, payload.as_bytes())?)
+    }
+
+    pub fn decode(frame: &Frame) -> Result<Self, Box<dyn std::error::Error>> {
+        if frame.framing == Framing::Binary && frame.verb == "image" {
+            let (header, runs) = image::decode_binary(&frame.payload)?;
+            return Ok(ClientMessage::Image(Image::from_runs(header, runs)?));
+        }
+        let (verb, payload) = (frame.verb.as_str(), frame.payload_str()?);
+        match verb {
+            "hello" => {
+                Ok(ClientMessage::Hello(serde_json::from_str(payload)?))
+            }
+            "motion" => {
+                Ok(ClientMessage::Motion(serde_json::from_str(payload)?))
+            }
+            "image" => {
+                Ok(ClientMessage::Image(serde_json::from_str(payload)?))
+            }
+            "get_image" => {
+                Ok(ClientMessage::GetImage(serde_json::from_str(payload)?))
+            }
+            "ack" => Ok(ClientMessage::Ack(payload.parse()?)),
+            "udp_hello" => {
+                Ok(ClientMessage::UdpHello(serde_json::from_str(payload)?))
+            }
+            "ping" => Ok(ClientMessage::Ping(payload.parse()?)),
+            "pong" => Ok(ClientMessage::Pong(payload.parse()?)),
+            "leave" => Ok(ClientMessage::Leave),
+            "chat" => Ok(ClientMessage::Chat(serde_json::from_str(payload)?)),
+            "list_rooms" => Ok(ClientMessage::ListRooms),
+            "create_room" => {
+                Ok(ClientMessage::CreateRoom(serde_json::from_str(payload)?))
+            }
+            "join_room" => {
+                Ok(ClientMessage::JoinRoom(serde_json::from_str(payload)?))
+            }
+            "leave_room" => Ok(ClientMessage::LeaveRoom),
+            _ => Err(format!("Message client inconnu : '{}'", verb).into()),
+        }
+    }
+
+    // Verbe que `decode` sait lire, pour distinguer un message inconnu d'un
+    // message mal formé
+    pub fn is_known(verb: &str) -> bool {
+        matches!(
+            verb,
+            "hello"
+                | "motion"
+                | "image"
+                | "get_image"
+                | "ack"
+                | "udp_hello"
+                | "ping"
+                | "pong"
+                | "leave"
+                | "chat"
+                | "list_rooms"
+                | "create_room"
+                | "join_room"
+                | "leave_room"
+        )
+    }
+}
+
+//~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Messages envoyés par le serveur aux clients
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMessage {
+    Welcome(Welcome),
+    Rejected(String),
+    // Les avatars ne sont désignés que par leur empreinte ; le contenu est
+    // envoyé à part dans `ImageData` lorsque le client le demande
+    Snapshot(Snapshot),
+    ImageData {
+        hash: ImageHash,
+        image: Image,
+    },
+    Positions(Positions), // Uniquement par datagramme UDP
+    Kicked(String),       // Raison de la déconnexion imposée par le serveur
+    Ping(u64),            // Signe de vie, renvoyé dans `pong`
+    Pong(u64),            // Réponse au `ping` du client
+    ServerShutdown(ServerShutdown),
+    Error(ErrorReply), // Message du client ignoré
+    Chat(ChatLine),
+    Rooms(Vec<RoomInfo>), // Réponse à `list_rooms`
+    Room(RoomInfo),       // Salon rejoint ; un état complet de son monde suit
+}
+
+impl ServerMessage {
+    pub fn encode(
+        &self,
+        framing: Framing,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (verb, payload) = match self {
+            // Plages de pixels brutes plutôt qu'en base64
+            ServerMessage::ImageData { hash, image }
+                if framing == Framing::Binary =>
+            {
+                let header = (hash, image.header());
+                let payload = image::encode_binary(&header, image)?;
+                return Ok(framing.frame("image_data", &payload)?);
+            }
+            ServerMessage::Welcome(welcome) => {
+                ("welcome", serde_json::to_string(welcome)?)
+            }
+            ServerMessage::Rejected(reason) => {
+                ("rejected", serde_json::to_string(reason)?)
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                ("snapshot", serde_json::to_string(snapshot)?)
+            }
+            ServerMessage::ImageData { hash, image } => {
+                ("image_data", serde_json::to_string(&(hash, image))?)
+            }
+            ServerMessage::Positions(positions) => {
+                ("positions", serde_json::to_string(positions)?)
+            }
+            ServerMessage::Kicked(reason) => {
+                ("kicked", serde_json::to_string(reason)?)
+            }
+            ServerMessage::Ping(seq) => ("ping", seq.to_string()),
+            ServerMessage::Pong(seq) => ("pong", seq.to_string()),
+            ServerMessage::ServerShutdown(shutdown) => {
+                ("server_shutdown", serde_json::to_string(shutdown)?)
+            }
+            ServerMessage::Error(reply) => {
+                ("error", serde_json::to_string(reply)?)
+            }
+            ServerMessage::Chat(line) => ("chat", serde_json::to_string(line)?),
+            ServerMessage::Rooms(rooms) => {
+                ("rooms", serde_json::to_string(rooms)?)
+            }
+            ServerMessage::Room(room) => ("room", serde_json::to_string(room)?),
+        };
+        Ok(framing.frame(verb, payload.as_bytes())?)
+    }
+
+    pub fn decode(frame: &Frame) -> Result<Self, Box<dyn std::error::Error>> {
+        if frame.framing == Framing::Binary && frame.verb == "image_data" {
+            let ((hash, header), runs) = image::decode_binary(&frame.payload)?;
+            let image = Image::from_runs(header, runs)?;
+            return Ok(ServerMessage::ImageData { hash, image });
+        }
+        let (verb, payload) = (frame.verb.as_str(), frame.payload_str()?);
+        match verb {
+            "welcome" => {
+                Ok(ServerMessage::Welcome(serde_json::from_str(payload)?))
+            }
+            "rejected" => {
+                Ok(ServerMessage::Rejected(serde_json::from_str(payload)?))
+            }
+            "snapshot" => {
+                Ok(ServerMessage::Snapshot(serde_json::from_str(payload)?))
+            }
+            "image_data" => {
+                let (hash, image) = serde_json::from_str(payload)?;
+                Ok(ServerMessage::ImageData { hash, image })
+            }
+            "positions" => {
+                Ok(ServerMessage::Positions(serde_json::from_str(payload)?))
+            }
+            "kicked" => {
+                Ok(ServerMessage::Kicked(serde_json::from_str(payload)?))
+            }
+            "ping" => Ok(ServerMessage::Ping(payload.parse()?)),
+            "pong" => Ok(ServerMessage::Pong(payload.parse()?)),
+            "server_shutdown" => Ok(ServerMessage::ServerShutdown(
+                serde_json::from_str(payload)?,
+            )),
+            "error" => Ok(ServerMessage::Error(serde_json::from_str(payload)?)),
+            "chat" => Ok(ServerMessage::Chat(serde_json::from_str(payload)?)),
+            "rooms" => Ok(ServerMessage::Rooms(serde_json::from_str(payload)?)),
+            "room" => Ok(ServerMessage::Room(serde_json::from_str(payload)?)),
+            _ => Err(format!("Message serveur inconnu : '{}'", verb).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framing::VERBS;
+    use std::collections::HashSet;
+
+    fn image() -> Image {
+        let mut pixels = vec![TRANSPARENT; 6];
+        pixels[4] = Color { r: 1, g: 2, b: 3 };
+        Image {
+            width: 3,
+            height: 2,
+            pixels,
+        }
+    }
+
+    fn room() -> RoomInfo {
+        RoomInfo {
+            name: "Donjon".to_string(),
+            width: 300,
+            height: 200,
+            players: 2,
+        }
+    }
+
+    // Un exemple de chaque message du client, avec les champs facultatifs
+    // présents pour qu'ils soient aussi vérifiés
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Hello(Hello {
+                version: PROTOCOL_VERSION,
+                capabilities: vec![BINARY_FRAMES.to_string()],
+                resume: Some("jeton".to_string()),
+                name: Some("Zoé".to_string()),
+            }),
+            ClientMessage::Motion(Motion {
+                seq: 7,
+                delta: Point { x: -10, y: 0 },
+            }),
+            ClientMessage::Image(image()),
+            ClientMessage::GetImage(image().hash()),
+            ClientMessage::Ack(12),
+            ClientMessage::UdpHello(UdpHello {
+                client_id: 3,
+                token: u64::MAX,
+            }),
+            ClientMessage::Ping(1),
+            ClientMessage::Pong(2),
+            ClientMessage::Leave,
+            ClientMessage::Chat("Bonjour à « tous » !".to_string()),
+            ClientMessage::ListRooms,
+            ClientMessage::CreateRoom(RoomRequest {
+                name: "Donjon".to_string(),
+                width: Some(300),
+                height: None,
+            }),
+            ClientMessage::JoinRoom("Donjon".to_string()),
+            ClientMessage::LeaveRoom,
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Welcome(Welcome {
+                client_id: 3,
+                world_width: 800,
+                world_height: 600,
+                server_version: "0.1.0".to_string(),
+                capabilities: vec![UDP_CHANNEL.to_string()],
+                udp: Some(UdpOffer {
+                    port: 5555,
+                    token: 99,
+                }),
+                heartbeat: Some(HeartbeatPolicy {
+                    interval_ms: 5000,
+                    misses: 3,
+                }),
+                session: Some(SessionOffer {
+                    token: "jeton".to_string(),
+                    grace_ms: 30000,
+                }),
+                resumed: true,
+                room: Some("accueil".to_string()),
+            }),
+            ServerMessage::Rejected("version non prise en charge".to_string()),
+            ServerMessage::Snapshot(Snapshot {
+                seq: 5,
+                base: Some(4),
+                time: 1234,
+                players: vec![PlayerState {
+                    id: 3,
+                    image: Some(image().hash()),
+                    name: Some("Zoé".to_string()),
+                    position: Some(Point { x: 1, y: 2 }),
+                }],
+                left: vec![1, 2],
+                last_input: Some(7),
+            }),
+            ServerMessage::ImageData {
+                hash: image().hash(),
+                image: image(),
+            },
+            ServerMessage::Positions(Positions {
+                seq: 5,
+                time: 1234,
+                players: vec![(3, Point { x: 1, y: 2 })],
+                last_input: None,
+            }),
+            ServerMessage::Kicked("trop de messages invalides".to_string()),
+            ServerMessage::Ping(3),
+            ServerMessage::Pong(4),
+            ServerMessage::ServerShutdown(ServerShutdown {
+                reason: "maintenance".to_string(),
+                restart_in: Some(60),
+            }),
+            ServerMessage::Error(ErrorReply {
+                code: ErrorCode::RateLimited,
+                verb: "motion".to_string(),
+                message: "trop de déplacements".to_string(),
+            }),
+            ServerMessage::Chat(ChatLine {
+                from: 3,
+                name: None,
+                text: "salut".to_string(),
+            }),
+            ServerMessage::Rooms(vec![room()]),
+            ServerMessage::Room(room()),
+        ]
+    }
+
+    // Encoder puis relire un message dans un format, en vérifiant que la
+    // trame est complète et seule ; retourne son verbe
+    fn roundtrip<M: PartialEq + std::fmt::Debug>(
+        framing: Framing,
+        message: &M,
+        encode: impl Fn(&M, Framing) -> Vec<u8>,
+        decode: impl Fn(&Frame) -> M,
+    ) -> String {
+        let mut decoder = FrameDecoder::new(framing);
+        decoder.extend(&encode(message, framing));
+        let frame = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(&decode(&frame), message, "{:?}", framing);
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        frame.verb
+    }
+
+    #[test]
+    fn every_verb_roundtrips_in_both_framings() {
+        for framing in [Framing::Lines, Framing::Binary] {
+            let mut verbs = HashSet::new();
+            for message in client_messages() {
+                verbs.insert(roundtrip(
+                    framing,
+                    &message,
+                    |m, f| m.encode(f).unwrap(),
+                    |frame| ClientMessage::decode(frame).unwrap(),
+                ));
+            }
+            for message in server_messages() {
+                verbs.insert(roundtrip(
+                    framing,
+                    &message,
+                    |m, f| m.encode(f).unwrap(),
+                    |frame| ServerMessage::decode(frame).unwrap(),
+                ));
+            }
+            let expected: HashSet<String> =
+                VERBS.iter().map(|v| v.to_string()).collect();
+            assert_eq!(verbs, expected, "{:?}", framing);
+        }
+    }
+
+    #[test]
+    fn client_verbs_are_known() {
+        for message in client_messages() {
+            let bytes = message.encode(Framing::Lines).unwrap();
+            let mut decoder = FrameDecoder::new(Framing::Lines);
+            decoder.extend(&bytes);
+            let frame = decoder.next_frame().unwrap().unwrap();
+            assert!(ClientMessage::is_known(&frame.verb), "{}", frame.verb);
+        }
+        assert!(!ClientMessage::is_known("welcome"));
+    }
+
+    #[test]
+    fn unknown_and_malformed_messages_are_errors() {
+        let frame = |verb: &str, payload: &str| Frame {
+            verb: verb.to_string(),
+            payload: payload.as_bytes().to_vec(),
+            framing: Framing::Lines,
+        };
+        assert!(ClientMessage::decode(&frame("welcome", "{}")).is_err());
+        assert!(ClientMessage::decode(&frame("motion", "{")).is_err());
+        assert!(ClientMessage::decode(&frame("ack", "-1")).is_err());
+        assert!(ServerMessage::decode(&frame("hello", "{}")).is_err());
+    }
+
+    #[test]
+    fn motion_stays_inside_the_world() {
+        let max = max_position(200, 100, 50, 20);
+        assert_eq!(max, Point { x: 150, y: 80 });
+        let at = |x, y| Point { x, y };
+        assert_eq!(apply_motion(at(10, 10), at(5, -3), max), at(15, 7));
+        assert_eq!(apply_motion(at(10, 10), at(-20, 500), max), at(0, 80));
+        // Sans débordement, même pour les déplacements extrêmes
+        let far = at(i32::MAX, i32::MIN);
+        assert_eq!(apply_motion(at(150, 0), far, max), at(150, 0));
+        let back = at(i32::MIN, i32::MAX);
+        assert_eq!(apply_motion(at(0, 80), back, max), at(0, 80));
+        // Sprite plus grand que le monde : collé à l'origine
+        let max = max_position(100, 100, 300, 20);
+        assert_eq!(apply_motion(at(0, 0), at(5, 5), max), at(0, 5));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::ServerState;
+use game_protocol::{Image, Point};
+use std::str::FromStr;
+
+// Collisions entre les avatars des joueurs d'un même salon, choisies avec
+// `--collision`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionMode {
+    None,   // Les avatars se traversent
+    Boxes,  // Les rectangles des avatars ne peuvent pas se chevaucher
+    Pixels, // Seuls leurs pixels non transparents ne le peuvent pas
+}
+
+impl FromStr for CollisionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "none" => Ok(CollisionMode::None),
+            "box" => Ok(CollisionMode::Boxes),
+            "pixel" => Ok(CollisionMode::Pixels),
+            _ => Err(format!(
+                "Mode de collision inconnu : {} (none, box ou pixel)",
+                value
+            )),
+        }
+    }
+}
+
+// Un avatar placé dans le monde
+#[derive(Clone, Copy)]
+struct Sprite<'a> {
+    position: Point,
+    image: &'a Image,
+}
+
+// Rectangle du monde : coin supérieur gauche inclus, coin inférieur droit
+// exclu
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: Point,
+    max: Point,
+}
+
+impl Bounds {
+    fn intersection(&self, other: &Bounds) -> Option<Bounds> {
+        let min = Point {
+            x: self.min.x.max(other.min.x),
+            y: self.min.y.max(other.min.y),
+        };
+        let max = Point {
+            x: self.max.x.min(other.max.x),
+            y: self.max.y.min(other.max.y),
+        };
+        (min.x < max.x && min.y < max.y).then_some(Bounds { min, max })
+    }
+}
+
+impl Sprite<'_> {
+    fn bounds(&self) -> Bounds {
+        Bounds {
+            min: self.position,
+            max: Point {
+                x: self.position.x + self.image.width as i32,
+                y: self.position.y + self.image.height as i32,
+            },
+        }
+    }
+
+    fn overlaps(&self, other: &Sprite, mode: CollisionMode) -> bool {
+        // Intersection des rectangles, dans les coordonnées du monde
+        let common = self.bounds().intersection(&other.bounds());
+        let Some(Bounds { min, max }) = common else {
+            return false;
+        };
+        let (left, top, right, bottom) = (min.x, min.y, max.x, max.y);
+        match mode {
+            CollisionMode::None => false,
+            CollisionMode::Boxes => true,
+            CollisionMode::Pixels => (top..bottom).any(|y| {
+                (left..right).any(|x| {
+                    self.is_opaque_at(x, y) && other.is_opaque_at(x, y)
+                })
+            }),
+        }
+    }
+
+    // Le point (x, y) du monde, supposé dans le rectangle de l'avatar, est
+    // couvert par un de ses pixels non transparents
+    fn is_opaque_at(&self, x: i32, y: i32) -> bool {
+        let x = (x - self.position.x) as usize;
+        let y = (y - self.position.y) as usize;
+        self.image.is_opaque(x, y)
+    }
+}
+
+// Position atteinte par le joueur `client_id` qui veut aller de `from` à
+// `to` : s'il heurterait un autre joueur de son salon, il s'arrête au
+// contact puis glisse le long de l'obstacle sur un seul axe, s'il le peut.
+// Les joueurs qui se chevauchent déjà (arrivée au même endroit) peuvent
+// s'écarter. Seuls les joueurs dans le rectangle balayé par le déplacement
+// sont examinés à chaque pas (la longueur des pas est bornée par
+// `--max-step`, obligatoire en mode `pixel`)
+pub fn resolve(
+    state: &ServerState,
+    client_id: u32,
+    from: Point,
+    to: Point,
+) -> Point {
+    let mode = state.collision;
+    if mode == CollisionMode::None || from == to {
+        return to;
+    }
+    let Some(client) = state.clients.get(&client_id) else {
+        return to;
+    };
+    let Some(image) = client.image.as_ref().and_then(|h| state.images.get(h))
+    else {
+        return to;
+    };
+
+    let start = Sprite {
+        position: from,
+        image,
+    };
+    let end = Sprite {
+        position: to,
+        image,
+    };
+    let (start_bounds, end_bounds) = (start.bounds(), end.bounds());
+    let swept = Bounds {
+        min: Point {
+            x: start_bounds.min.x.min(end_bounds.min.x),
+            y: start_bounds.min.y.min(end_bounds.min.y),
+        },
+        max: Point {
+            x: start_bounds.max.x.max(end_bounds.max.x),
+            y: start_bounds.max.y.max(end_bounds.max.y),
+        },
+    };
+    let obstacles: Vec<Sprite> = state
+        .clients
+        .iter()
+        .filter(|(&id, other)| id != client_id && other.room == client.room)
+        .filter_map(|(_, other)| {
+            let image = state.images.get(other.image.as_ref()?)?;
+            Some(Sprite {
+                position: other.position,
+                image,
+            })
+        })
+        .filter(|obstacle| obstacle.bounds().intersection(&swept).is_some())
+        .filter(|obstacle| !start.overlaps(obstacle, mode))
+        .collect();
+
+    let is_free = |position: Point| {
+        let moved = Sprite { position, image };
+        obstacles.iter().all(|obstacle| !moved.overlaps(obstacle, mode))
+    };
+    let contact = approach(from, to, is_free);
+    if contact == to {
+        return to;
+    }
+    [Point { x: to.x, y: contact.y }, Point { x: contact.x, y: to.y }]
+        .into_iter()
+        .map(|target| approach(contact, target, is_free))
+        .find(|&slid| slid != contact)
+        .unwrap_or(contact)
+}
+
+// Avancer pixel par pixel de `from` vers `to` tant que la place est libre
+fn approach(from: Point, to: Point, is_free: impl Fn(Point) -> bool) -> Point {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let steps = dx.abs().max(dy.abs());
+    let mut reached = from;
+    for step in 1..=steps {
+        let position = Point {
+            x: from.x + dx * step / steps,
+            y: from.y + dy * step / steps,
+        };
+        if !is_free(position) {
+            break;
+        }
+        reached = position;
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::LOBBY;
+    use crate::testing;
+    use game_protocol::{Color, TRANSPARENT};
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+
+    fn at(x: i32, y: i32) -> Point {
+        Point { x, y }
+    }
+
+    // Carré plein de 10 pixels de côté
+    fn square() -> Image {
+        Image {
+            width: 10,
+            height: 10,
+            pixels: vec![RED; 100],
+        }
+    }
+
+    // Carré de 10 pixels dont seul le quart supérieur gauche est opaque
+    fn corner() -> Image {
+        let pixels = (0..100)
+            .map(|i| if i % 10 < 5 && i / 10 < 5 { RED } else { TRANSPARENT })
+            .collect();
+        Image {
+            width: 10,
+            height: 10,
+            pixels,
+        }
+    }
+
+    // Joueur 1 en `from` et joueur 2, l'obstacle, en `obstacle`
+    fn world(
+        mode: CollisionMode,
+        image: Image,
+        from: Point,
+        obstacle: Point,
+    ) -> ServerState {
+        let mut state = testing::state(|config| config.collision = mode);
+        testing::add_player(&mut state, 1, LOBBY, from, Some(image.clone()));
+        testing::add_player(&mut state, 2, LOBBY, obstacle, Some(image));
+        state
+    }
+
+    #[test]
+    fn boxes_overlap_only_when_rectangles_intersect() {
+        let image = square();
+        let sprite = |position| Sprite {
+            position,
+            image: &image,
+        };
+        let mode = CollisionMode::Boxes;
+        assert!(sprite(at(0, 0)).overlaps(&sprite(at(9, 9)), mode));
+        assert!(!sprite(at(0, 0)).overlaps(&sprite(at(10, 0)), mode));
+        assert!(!sprite(at(0, 0)).overlaps(&sprite(at(0, -10)), mode));
+        let none = CollisionMode::None;
+        assert!(!sprite(at(0, 0)).overlaps(&sprite(at(0, 0)), none));
+    }
+
+    #[test]
+    fn pixels_overlap_only_where_both_are_opaque() {
+        let image = corner();
+        let sprite = |position| Sprite {
+            position,
+            image: &image,
+        };
+        // Rectangles qui se chevauchent, mais pas les quarts opaques
+        let (a, b) = (sprite(at(0, 0)), sprite(at(5, 0)));
+        assert!(a.overlaps(&b, CollisionMode::Boxes));
+        assert!(!a.overlaps(&b, CollisionMode::Pixels));
+        // Quarts opaques qui se touchent sur une colonne
+        assert!(a.overlaps(&sprite(at(4, 4)), CollisionMode::Pixels));
+    }
+
+    #[test]
+    fn player_stops_at_contact() {
+        let state = world(CollisionMode::Boxes, square(), at(0, 0), at(0, 20));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(0, 30)), at(0, 10));
+        // Avec les masques, l'avatar en coin s'avance jusqu'aux pixels
+        let state = world(CollisionMode::Pixels, corner(), at(0, 0), at(0, 20));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(0, 30)), at(0, 15));
+        // Sans collisions, rien n'arrête le déplacement
+        let state = world(CollisionMode::None, square(), at(0, 0), at(0, 20));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(0, 30)), at(0, 30));
+    }
+
+    #[test]
+    fn blocked_player_slides_along_one_axis() {
+        let state = world(CollisionMode::Boxes, square(), at(0, 0), at(0, 20));
+        // Contact en (2, 10), puis glissement horizontal jusqu'à x = 4
+        assert_eq!(resolve(&state, 1, at(0, 0), at(4, 20)), at(4, 10));
+        // Le long d'un obstacle placé à droite, glissement vertical
+        let state = world(CollisionMode::Boxes, square(), at(0, 0), at(20, 0));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(20, 3)), at(10, 3));
+    }
+
+    #[test]
+    fn overlapping_players_can_move_apart() {
+        let state = world(CollisionMode::Boxes, square(), at(0, 0), at(5, 5));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(-10, 0)), at(-10, 0));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(3, 3)), at(3, 3));
+    }
+
+    #[test]
+    fn players_outside_the_swept_area_or_room_are_ignored() {
+        let mut state =
+            world(CollisionMode::Boxes, square(), at(0, 0), at(100, 100));
+        assert_eq!(resolve(&state, 1, at(0, 0), at(0, 50)), at(0, 50));
+        let elsewhere = Some(square());
+        testing::add_player(&mut state, 3, "ailleurs", at(0, 20), elsewhere);
+        assert_eq!(resolve(&state, 1, at(0, 0), at(0, 50)), at(0, 50));
+    }
+}
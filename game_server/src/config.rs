@@ -0,0 +1,215 @@
+use crate::collision::CollisionMode;
+use crate::rooms::{MAX_SIDE, MIN_SIDE};
+use std::time::Duration;
+
+// Configuration du serveur, lue depuis la ligne de commande :
+//   game_server [--port N] [--tick-rate N] [--udp-port N] [--ws-port N]
+//               [--world-width N] [--world-height N]
+//               [--event-loop] [--heartbeat S] [--heartbeat-misses N]
+//               [--session-grace S] [--max-invalid N] [--invalid-window S]
+//               [--max-step N] [--motion-rate N] [--motion-burst N]
+//               [--collision none|box|pixel]
+//               [--max-avatar-side N] [--max-avatar-bytes N]
+//               [--chat-rate N] [--chat-burst N] [--max-rooms N]
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub tick_rate: f64, // Pas de simulation par seconde
+    pub world_width: usize, // Monde du salon d'accueil, envoyé aux clients dans `welcome`
+    pub world_height: usize,
+    pub udp_port: Option<u16>, // Canal UDP proposé aux clients (`None` : désactivé)
+    pub ws_port: Option<u16>, // Passerelle WebSocket (`None` : désactivée)
+    pub event_loop: bool, // Toutes les connexions dans un seul thread, sans verrou
+    pub heartbeat: Option<Duration>, // Intervalle des `ping` (`None` : pas de surveillance)
+    pub heartbeat_misses: u32, // Intervalles sans message avant de déconnecter un client
+    pub session_grace: Option<Duration>, // Délai de reprise après une coupure (`None` : désactivée)
+    pub max_invalid: Option<usize>, // Messages invalides tolérés par fenêtre (`None` : sans limite)
+    pub invalid_window: Duration, // Fenêtre glissante de `max_invalid`
+    pub max_step: Option<u32>, // Amplitude maximale d'un déplacement (`None` : aucune)
+    pub motion_rate: Option<f64>, // Déplacements par seconde par client (`None` : sans limite)
+    pub motion_burst: f64, // Déplacements acceptés d'affilée
+    pub collision: CollisionMode, // Collisions entre les avatars d'un même salon
+    pub max_avatar_side: Option<usize>, // Largeur et hauteur maximales d'un avatar (`None` : aucune)
+    pub max_avatar_bytes: Option<usize>, // Taille maximale des pixels d'un avatar (`None` : aucune)
+    pub chat_rate: Option<f64>, // Messages de discussion par seconde par client (`None` : sans limite)
+    pub chat_burst: f64, // Messages de discussion acceptés d'affilée
+    pub max_rooms: Option<usize>, // Salons ouverts en plus de l'accueil (`None` : sans limite)
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            port: 5555,
+            tick_rate: 30.0,
+            world_width: 800,
+            world_height: 600,
+            udp_port: None,
+            ws_port: Some(8080),
+            event_loop: false,
+            heartbeat: Some(Duration::from_secs(5)),
+            heartbeat_misses: 3,
+            session_grace: Some(Duration::from_secs(30)),
+            max_invalid: Some(10),
+            invalid_window: Duration::from_secs(10),
+            max_step: Some(10),
+            motion_rate: Some(60.0),
+            motion_burst: 20.0,
+            collision: CollisionMode::None,
+            max_avatar_side: Some(256),
+            max_avatar_bytes: Some(64 * 1024),
+            chat_rate: Some(1.0),
+            chat_burst: 5.0,
+            max_rooms: Some(16),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_args(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = ServerConfig::default();
+        let mut udp_port = None;
+        let mut ws_port = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Valeur manquante pour {}", arg))
+            };
+            match arg.as_str() {
+                "--port" => config.port = value()?.parse()?,
+                "--tick-rate" => config.tick_rate = value()?.parse()?,
+                "--world-width" => config.world_width = value()?.parse()?,
+                "--world-height" => config.world_height = value()?.parse()?,
+                "--udp-port" => udp_port = Some(value()?.parse()?),
+                "--ws-port" => ws_port = Some(value()?.parse()?),
+                "--event-loop" => config.event_loop = true,
+                // 0 désactive la surveillance
+                "--heartbeat" => config.heartbeat = seconds(value()?)?,
+                // 0 désactive la reprise de session
+                "--session-grace" => config.session_grace = seconds(value()?)?,
+                "--heartbeat-misses" => {
+                    config.heartbeat_misses = value()?.parse()?;
+                    if config.heartbeat_misses == 0 {
+                        return Err("--heartbeat-misses doit être au moins 1"
+                            .into());
+                    }
+                }
+                // 0 désactive la limite
+                "--max-invalid" => {
+                    config.max_invalid =
+                        Some(value()?.parse()?).filter(|max| *max != 0)
+                }
+                "--invalid-window" => {
+                    config.invalid_window = seconds(value()?)?
+                        .ok_or("--invalid-window doit être positif")?
+                }
+                // 0 désactive les limites
+                "--max-step" => {
+                    config.max_step =
+                        Some(value()?.parse()?).filter(|max| *max != 0)
+                }
+                "--motion-rate" => {
+                    config.motion_rate =
+                        Some(value()?.parse()?).filter(|rate| *rate != 0.0)
+                }
+                "--motion-burst" => config.motion_burst = value()?.parse()?,
+                "--collision" => config.collision = value()?.parse()?,
+                // 0 désactive les limites
+                "--max-avatar-side" => {
+                    config.max_avatar_side =
+                        Some(value()?.parse()?).filter(|max| *max != 0)
+                }
+                "--max-avatar-bytes" => {
+                    config.max_avatar_bytes =
+                        Some(value()?.parse()?).filter(|max| *max != 0)
+                }
+                // 0 désactive la limite
+                "--chat-rate" => {
+                    config.chat_rate =
+                        Some(value()?.parse()?).filter(|rate| *rate != 0.0)
+                }
+                "--chat-burst" => config.chat_burst = value()?.parse()?,
+                // 0 désactive la limite
+                "--max-rooms" => {
+                    config.max_rooms =
+                        Some(value()?.parse()?).filter(|max| *max != 0)
+                }
+                _ => return Err(format!("Option inconnue : {}", arg).into()),
+            }
+        }
+
+        if !(config.tick_rate > 0.0 && config.tick_rate.is_finite()) {
+            return Err(format!(
+                "Fréquence de simulation invalide : {}",
+                config.tick_rate
+            )
+            .into());
+        }
+
+        let sides = MIN_SIDE..=MAX_SIDE;
+        if !sides.contains(&config.world_width)
+            || !sides.contains(&config.world_height)
+        {
+            return Err(format!(
+                "Monde de {}×{} invalide : chaque côté doit faire de {} \
+                 à {} pixels",
+                config.world_width, config.world_height, MIN_SIDE, MAX_SIDE
+            )
+            .into());
+        }
+
+        // Le seau doit pouvoir contenir au moins un déplacement
+        let burst = config.motion_burst;
+        let rate_valid =
+            config.motion_rate.is_none_or(|r| r > 0.0 && r.is_finite());
+        if !(rate_valid && burst >= 1.0 && burst.is_finite()) {
+            return Err("Limites de déplacement invalides".into());
+        }
+        let burst = config.chat_burst;
+        let rate_valid =
+            config.chat_rate.is_none_or(|r| r > 0.0 && r.is_finite());
+        if !(rate_valid && burst >= 1.0 && burst.is_finite()) {
+            return Err("Limites de discussion invalides".into());
+        }
+
+        // Chaque pas d'un déplacement examine les masques des avatars
+        // voisins : sans limite, un seul message pourrait traverser le monde
+        // pixel par pixel pendant que l'état est verrouillé
+        let pixels = config.collision == CollisionMode::Pixels;
+        if pixels && config.max_step.is_none() {
+            return Err("--collision pixel demande une limite --max-step".into());
+        }
+
+        // Par défaut, UDP sur le même numéro de port que TCP ; 0 le désactive
+        config.udp_port = match udp_port {
+            None => Some(config.port),
+            Some(0) => None,
+            Some(port) => Some(port),
+        };
+
+        // 0 désactive la passerelle WebSocket ; elle repose sur les threads
+        // et n'est donc pas lancée par défaut avec --event-loop
+        config.ws_port = match ws_port {
+            Some(0) => None,
+            Some(_) if config.event_loop => {
+                return Err("--ws-port n'est pas disponible avec --event-loop \
+                            (0 pour désactiver la passerelle)"
+                    .into())
+            }
+            Some(port) => Some(port),
+            None if config.event_loop => None,
+            None => config.ws_port,
+        };
+
+        Ok(config)
+    }
+}
+
+// Durée en secondes ; 0 désactive la fonctionnalité concernée
+fn seconds(value: &str) -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+    let seconds: f64 = value.parse()?;
+    if !(seconds >= 0.0 && seconds.is_finite()) {
+        return Err(format!("Durée invalide : {}", value).into());
+    }
+    Ok((seconds > 0.0).then(|| Duration::from_secs_f64(seconds)))
+}
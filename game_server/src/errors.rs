@@ -0,0 +1,85 @@
+use crate::ServerState;
+use game_protocol::{ErrorCode, ErrorReply, ServerMessage};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Nombre de messages invalides tolérés d'un client sur une fenêtre glissante
+// avant de le déconnecter
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorPolicy {
+    pub max: usize,
+    pub window: Duration,
+}
+
+// Prévenir le client que son message a été ignoré
+pub fn reply(
+    state: &mut ServerState,
+    client_id: u32,
+    code: ErrorCode,
+    verb: &str,
+    message: String,
+) {
+    eprintln!(
+        "Client {} : '{}' ignoré ({}) : {}",
+        client_id, verb, code, message
+    );
+    let Some(client) = state.clients.get_mut(&client_id) else {
+        return;
+    };
+    let reply = ServerMessage::Error(ErrorReply {
+        code,
+        verb: verb.to_string(),
+        message,
+    });
+    if let Err(e) = client.send(&reply) {
+        crate::evict(state, client_id, &e.to_string());
+    }
+}
+
+// Message que le client n'aurait pas dû envoyer : répondre, puis le
+// déconnecter s'il en envoie trop
+pub fn invalid(
+    state: &mut ServerState,
+    client_id: u32,
+    code: ErrorCode,
+    verb: &str,
+    message: String,
+) {
+    reply(state, client_id, code, verb, message);
+    let Some(client) = state.clients.get_mut(&client_id) else {
+        return;
+    };
+    *client.violations.entry(code).or_default() += 1;
+    let Some(policy) = state.error_policy else {
+        return;
+    };
+
+    let now = Instant::now();
+    client.invalid.push_back(now);
+    while client
+        .invalid
+        .front()
+        .is_some_and(|&at| now.saturating_duration_since(at) > policy.window)
+    {
+        client.invalid.pop_front();
+    }
+    if client.invalid.len() > policy.max {
+        let reason = format!(
+            "{} messages invalides en moins de {:?}",
+            client.invalid.len(),
+            policy.window
+        );
+        crate::evict(state, client_id, &reason);
+    }
+}
+
+// Décompte des messages refusés d'un client, pour la modération :
+// « malformed × 2, rate_limited × 40 »
+pub fn summary(violations: &HashMap<ErrorCode, u32>) -> String {
+    let mut counts: Vec<String> = violations
+        .iter()
+        .map(|(code, count)| format!("{} × {}", code, count))
+        .collect();
+    counts.sort();
+    counts.join(", ")
+}
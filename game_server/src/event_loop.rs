@@ -0,0 +1,288 @@
+use crate::outbox::Outbox;
+use crate::{shutdown, simulation, udp, ServerState};
+use crate::{HELLO_TIMEOUT, MAX_HELLO_LEN};
+use game_protocol::{Frame, FrameDecoder, Framing};
+use std::io::{Error, ErrorKind, Read};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+// Taille des lectures sur les connexions des clients
+const READ_BUFFER: usize = 64 * 1024;
+
+// Lectures au plus par connexion à chaque réveil, pour qu'un client qui
+// envoie sans arrêt ne retarde pas les autres ni la simulation
+const MAX_READS: usize = 4;
+
+// Connexion d'un client, enregistré une fois `hello` reçu
+struct Connection {
+    stream: TcpStream,
+    decoder: FrameDecoder,
+    client: Option<(u32, u64)>, // Identifiant du client et de la connexion
+    opened: Instant, // Ouverture de la connexion, pour `HELLO_TIMEOUT`
+}
+
+// Cœur du serveur en un seul thread : toutes les connexions sont non
+// bloquantes et surveillées par `poll`, l'état du monde n'est donc jamais
+// partagé. Le réveil suit aussi le rythme de la simulation
+pub fn run(
+    listener: TcpListener,
+    udp: Option<UdpSocket>,
+    mut state: ServerState,
+    tick_rate: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    listener.set_nonblocking(true)?;
+    if let Some(socket) = &udp {
+        socket.set_nonblocking(true)?;
+    }
+    println!("Boucle d'événements : toutes les connexions dans un seul thread");
+
+    let period = Duration::from_secs_f64(1.0 / tick_rate);
+    let mut next_tick = Instant::now() + period;
+    let mut connections: Vec<Connection> = Vec::new();
+    let mut buffer = vec![0u8; READ_BUFFER.max(udp::MAX_DATAGRAM)];
+
+    loop {
+        // Surveiller l'écriture seulement pour les clients qui attendent
+        let mut fds = vec![pollfd(&listener, libc::POLLIN)];
+        if let Some(socket) = &udp {
+            fds.push(pollfd(socket, libc::POLLIN));
+        }
+        let first = fds.len();
+        for connection in &connections {
+            let pending = connection
+                .client
+                .filter(|&(id, conn)| crate::is_current(&state, id, conn))
+                .and_then(|(id, _)| state.clients[&id].outbox.as_ref())
+                .is_some_and(Outbox::is_pending);
+            let events = if pending {
+                libc::POLLIN | libc::POLLOUT
+            } else {
+                libc::POLLIN
+            };
+            fds.push(pollfd(&connection.stream, events));
+        }
+
+        poll(&mut fds, next_tick.saturating_duration_since(Instant::now()))?;
+
+        if fds[0].revents != 0 {
+            accept(&listener, &mut connections);
+        }
+        if let Some(socket) = udp.as_ref().filter(|_| fds[1].revents != 0) {
+            receive_datagrams(socket, &mut state, &mut buffer);
+        }
+
+        let mut closed = Vec::new();
+        for (i, fd) in fds[first..].iter().enumerate() {
+            let readable = libc::POLLIN | libc::POLLHUP | libc::POLLERR;
+            if fd.revents & readable != 0
+                && !receive(&mut connections[i], &mut state, &mut buffer)
+            {
+                closed.push(i);
+            }
+        }
+        // Connexions restées muettes : elles n'occupent plus de place
+        for (i, connection) in connections.iter().enumerate() {
+            if connection.client.is_none()
+                && connection.opened.elapsed() > HELLO_TIMEOUT
+                && !closed.contains(&i)
+            {
+                eprintln!(
+                    "Connexion fermée : pas de 'hello' après {:?}",
+                    HELLO_TIMEOUT
+                );
+                closed.push(i);
+            }
+        }
+        closed.sort_unstable();
+
+        // Le socket d'écoute est fermé au retour : plus aucune connexion
+        if let Some(notice) = shutdown::requested() {
+            shutdown::shut_down(&mut state, notice);
+            return Ok(());
+        }
+
+        if Instant::now() >= next_tick {
+            next_tick += period;
+            simulation::tick(&mut state);
+        }
+
+        // Indices décroissants : `swap_remove` ne déplace qu'une connexion
+        // déjà examinée
+        for i in closed.into_iter().rev() {
+            let connection = connections.swap_remove(i);
+            if let Some((client_id, current)) = connection.client {
+                crate::handle_disconnect(client_id, current, &mut state);
+            }
+        }
+
+        flush(&mut state);
+    }
+}
+
+fn pollfd(socket: &impl AsRawFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events,
+        revents: 0,
+    }
+}
+
+// Attendre qu'un descripteur soit prêt ou que le délai expire
+fn poll(fds: &mut [libc::pollfd], timeout: Duration) -> Result<(), Error> {
+    // Arrondi au-dessus pour ne pas se réveiller juste avant le pas
+    let timeout = timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128);
+    // SAFETY: `fds` est un tableau valide de `fds.len()` éléments pendant
+    // tout l'appel
+    let ready = unsafe {
+        libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout as i32)
+    };
+    if ready < 0 {
+        let e = Error::last_os_error();
+        // Interrompu par un signal : les `revents` sont restés à zéro
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn accept(listener: &TcpListener, connections: &mut Vec<Connection>) {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = stream.set_nonblocking(true) {
+                    eprintln!("Erreur de connexion entrante : {}", e);
+                    continue;
+                }
+                connections.push(Connection {
+                    stream,
+                    decoder: FrameDecoder::new(Framing::Lines),
+                    client: None,
+                    opened: Instant::now(),
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                eprintln!("Erreur de connexion entrante : {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn receive_datagrams(
+    socket: &UdpSocket,
+    state: &mut ServerState,
+    buffer: &mut [u8],
+) {
+    loop {
+        match socket.recv_from(buffer) {
+            Ok((len, from)) => udp::handle_datagram(&buffer[..len], from, state),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => eprintln!("Erreur de réception UDP : {}", e),
+        }
+    }
+}
+
+// Lire ce qui est disponible (au plus `MAX_READS` fois, la suite attend le
+// prochain réveil) et traiter les trames complètes ; `false` si la connexion
+// doit être fermée
+fn receive(
+    connection: &mut Connection,
+    state: &mut ServerState,
+    buffer: &mut [u8],
+) -> bool {
+    let mut reads = 0;
+    while reads < MAX_READS {
+        let len = match connection.stream.read(buffer) {
+            Ok(0) => return false,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Erreur : {}", e);
+                return false;
+            }
+        };
+        connection.decoder.extend(&buffer[..len]);
+        loop {
+            let frame = match connection.decoder.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Erreur : {}", e);
+                    return false;
+                }
+            };
+            match handle_frame(connection, state, &frame) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => {
+                    eprintln!("Erreur : {}", e);
+                    return false;
+                }
+            }
+        }
+        reads += 1;
+        if connection.client.is_none()
+            && connection.decoder.pending() > MAX_HELLO_LEN
+        {
+            eprintln!(
+                "Connexion fermée : plus de {} octets avant 'hello'",
+                MAX_HELLO_LEN
+            );
+            return false;
+        }
+    }
+    true
+}
+
+fn handle_frame(
+    connection: &mut Connection,
+    state: &mut ServerState,
+    frame: &Frame,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some((client_id, current)) = connection.client else {
+        // Poignée de main : le client doit d'abord envoyer `hello`
+        let checked = crate::check_hello(frame)
+            .and_then(|hello| crate::admit(state, &hello).map(|_| hello));
+        let hello = match checked {
+            Ok(hello) => hello,
+            Err(reason) => {
+                crate::reject(&connection.stream, false, reason)?;
+                return Ok(false);
+            }
+        };
+        let stream = &connection.stream;
+        let (client_id, current, framing) =
+            crate::register_client(state, hello, false, |_| {
+                Outbox::buffered(stream)
+            })?;
+        connection.decoder.set_framing(framing);
+        connection.client = Some((client_id, current));
+        return Ok(true);
+    };
+
+    // Client évincé, parti ou repris sur une autre connexion : celle-ci est
+    // déjà en cours de fermeture
+    if !crate::is_current(state, client_id, current) {
+        return Ok(false);
+    }
+    crate::handle_message(client_id, frame, state)?;
+    Ok(true)
+}
+
+// Écrire les messages en attente ; les clients qui ne lisent plus sont
+// évincés comme avec les threads d'écriture
+fn flush(state: &mut ServerState) {
+    let mut evicted = Vec::new();
+    for (&id, client) in &mut state.clients {
+        if let Some(Err(e)) = client.outbox.as_mut().map(Outbox::flush) {
+            evicted.push((id, e));
+        }
+    }
+    for (id, reason) in evicted {
+        crate::evict(state, id, &reason);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::ServerState;
+use game_protocol::{HeartbeatPolicy, ServerMessage};
+use std::time::{Duration, Instant};
+
+// Surveillance des connexions : un `ping` part vers tous les clients à
+// chaque intervalle, et un client dont on n'a rien reçu pendant plusieurs
+// intervalles est déconnecté (connexion à demi ouverte, client figé)
+#[derive(Debug)]
+pub struct Heartbeat {
+    policy: HeartbeatPolicy,
+    seq: u64, // Numéro du dernier `ping`
+    next_ping: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(policy: HeartbeatPolicy) -> Self {
+        Heartbeat {
+            policy,
+            seq: 0,
+            next_ping: Instant::now(),
+        }
+    }
+
+    pub fn policy(&self) -> HeartbeatPolicy {
+        self.policy
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.policy.interval_ms)
+    }
+}
+
+// Appelée à chaque pas de simulation
+pub fn check(state: &mut ServerState) {
+    let Some(heartbeat) = state.heartbeat.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    let timeout = heartbeat.interval() * heartbeat.policy.misses;
+    let ping = (now >= heartbeat.next_ping).then(|| {
+        heartbeat.seq += 1;
+        heartbeat.next_ping = now + heartbeat.interval();
+        heartbeat.seq
+    });
+
+    let mut evicted = Vec::new();
+    for (&id, client) in &mut state.clients {
+        // Client déconnecté en attente de reprise : voir `session::expire`
+        if client.detached.is_some() {
+            continue;
+        }
+        let silent = now.saturating_duration_since(client.last_seen);
+        if silent > timeout {
+            evicted.push((
+                id,
+                format!("aucun message depuis {:.1} s", silent.as_secs_f64()),
+            ));
+            continue;
+        }
+        if let Some(seq) = ping {
+            if let Err(e) = client.send(&ServerMessage::Ping(seq)) {
+                evicted.push((id, e.to_string()));
+            }
+        }
+    }
+
+    // Les autres joueurs verront le départ dans le prochain instantané
+    for (id, reason) in evicted {
+        crate::evict(state, id, &reason);
+    }
+}
@@ -0,0 +1,249 @@
+use game_protocol::{check_chat, ErrorCode, Image, Motion};
+use std::time::Instant;
+
+// Limites imposées aux déplacements envoyés par les clients, pour qu'un
+// client modifié ne puisse ni se téléporter ni inonder le serveur
+#[derive(Debug, Clone, Copy)]
+pub struct MotionPolicy {
+    pub max_step: Option<u32>, // Amplitude maximale sur chaque axe (`None` : aucune)
+    pub rate: Option<f64>, // Déplacements par seconde en régime continu (`None` : aucune limite)
+    pub burst: f64, // Déplacements acceptés d'affilée après une pause
+}
+
+// Limites imposées aux avatars, conservés en mémoire et renvoyés à tous les
+// clients
+#[derive(Debug, Clone, Copy)]
+pub struct ImagePolicy {
+    pub max_side: Option<usize>, // Largeur et hauteur maximales (`None` : aucune)
+    pub max_bytes: Option<usize>, // Taille maximale des pixels, 3 octets chacun (`None` : aucune)
+}
+
+// Limite de débit des messages de discussion, relayés à tous les joueurs
+#[derive(Debug, Clone, Copy)]
+pub struct ChatPolicy {
+    pub rate: Option<f64>, // Messages par seconde en régime continu (`None` : aucune limite)
+    pub burst: f64, // Messages acceptés d'affilée après une pause
+}
+
+// Seau à jetons : il se remplit au rythme autorisé jusqu'à sa capacité, et
+// chaque message accepté en consomme un
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+    warned: bool, // Refus déjà signalé depuis le dernier message accepté
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64) -> Self {
+        TokenBucket {
+            tokens: capacity,
+            refilled: Instant::now(),
+            warned: false,
+        }
+    }
+
+    // Premier refus depuis le dernier message accepté : un client qui
+    // insiste n'est prévenu qu'une fois par épuisement du seau
+    pub fn first_refusal(&mut self) -> bool {
+        !std::mem::replace(&mut self.warned, true)
+    }
+
+    fn take(&mut self, rate: f64, capacity: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        self.warned = false;
+        true
+    }
+}
+
+// Vérifier un déplacement avant de le mettre en attente ; l'erreur contient
+// le code et la raison renvoyés au client
+pub fn check_motion(
+    policy: &MotionPolicy,
+    bucket: &mut TokenBucket,
+    motion: &Motion,
+) -> Result<(), (ErrorCode, String)> {
+    if let Some(rate) = policy.rate {
+        if !bucket.take(rate, policy.burst) {
+            return Err((
+                ErrorCode::RateLimited,
+                format!("plus de {} déplacements par seconde", rate),
+            ));
+        }
+    }
+    if let Some(max_step) = policy.max_step {
+        let (x, y) = (motion.delta.x, motion.delta.y);
+        let step = x.unsigned_abs().max(y.unsigned_abs());
+        if step > max_step {
+            return Err((
+                ErrorCode::InvalidMotion,
+                format!(
+                    "déplacement de {} pixels, au plus {} autorisés",
+                    step, max_step
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Vérifier un message de discussion avant de le relayer ; l'erreur contient
+// le code et la raison renvoyés au client
+pub fn check_chat_message(
+    policy: &ChatPolicy,
+    bucket: &mut TokenBucket,
+    text: &str,
+) -> Result<(), (ErrorCode, String)> {
+    check_chat(text).map_err(|reason| (ErrorCode::InvalidChat, reason))?;
+    if let Some(rate) = policy.rate {
+        if !bucket.take(rate, policy.burst) {
+            return Err((
+                ErrorCode::RateLimited,
+                format!("plus de {} messages par seconde", rate),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Vérifier un avatar avant de le conserver ; l'erreur contient la raison
+// renvoyée au client
+pub fn check_image(policy: &ImagePolicy, image: &Image) -> Result<(), String> {
+    if !image.is_consistent() {
+        return Err(format!(
+            "{} pixels pour une image de {}×{}",
+            image.pixels.len(),
+            image.width,
+            image.height
+        ));
+    }
+    if image.width == 0 || image.height == 0 {
+        return Err("image vide".to_string());
+    }
+    if let Some(max_side) = policy.max_side {
+        if image.width > max_side || image.height > max_side {
+            return Err(format!(
+                "image de {}×{}, au plus {}×{} autorisé",
+                image.width, image.height, max_side, max_side
+            ));
+        }
+    }
+    if let Some(max_bytes) = policy.max_bytes {
+        let bytes = image.pixels.len() * 3;
+        if bytes > max_bytes {
+            return Err(format!(
+                "image de {} octets, au plus {} autorisés",
+                bytes, max_bytes
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game_protocol::Point;
+    use std::time::Duration;
+
+    fn motion(x: i32, y: i32) -> Motion {
+        Motion {
+            seq: 1,
+            delta: Point { x, y },
+        }
+    }
+
+    // Faire comme si le dernier remplissage datait de `elapsed`
+    fn wait(bucket: &mut TokenBucket, elapsed: Duration) {
+        bucket.refilled -= elapsed;
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_at_the_rate() {
+        let mut bucket = TokenBucket::new(3.0);
+        assert!((0..3).all(|_| bucket.take(10.0, 3.0)));
+        assert!(!bucket.take(10.0, 3.0));
+        // Un dixième de seconde à 10 par seconde : un seul jeton de plus
+        wait(&mut bucket, Duration::from_millis(110));
+        assert!(bucket.take(10.0, 3.0));
+        assert!(!bucket.take(10.0, 3.0));
+        // Une longue pause ne remplit pas au-delà de la capacité
+        wait(&mut bucket, Duration::from_secs(60));
+        assert!((0..3).all(|_| bucket.take(10.0, 3.0)));
+        assert!(!bucket.take(10.0, 3.0));
+    }
+
+    #[test]
+    fn refusal_is_reported_once_until_a_message_is_accepted() {
+        let mut bucket = TokenBucket::new(1.0);
+        assert!(bucket.take(1.0, 1.0));
+        assert!(!bucket.take(1.0, 1.0));
+        assert!(bucket.first_refusal());
+        assert!(!bucket.take(1.0, 1.0));
+        assert!(!bucket.first_refusal());
+        wait(&mut bucket, Duration::from_secs(1));
+        assert!(bucket.take(1.0, 1.0));
+        assert!(!bucket.take(1.0, 1.0));
+        assert!(bucket.first_refusal());
+    }
+
+    #[test]
+    fn motions_are_limited_by_rate_and_step() {
+        let policy = MotionPolicy {
+            max_step: Some(10),
+            rate: Some(1.0),
+            burst: 2.0,
+        };
+        let mut bucket = TokenBucket::new(policy.burst);
+        assert!(check_motion(&policy, &mut bucket, &motion(10, -10)).is_ok());
+        let refused = check_motion(&policy, &mut bucket, &motion(11, 0));
+        assert_eq!(refused.unwrap_err().0, ErrorCode::InvalidMotion);
+        let refused = check_motion(&policy, &mut bucket, &motion(0, 0));
+        assert_eq!(refused.unwrap_err().0, ErrorCode::RateLimited);
+    }
+
+    #[test]
+    fn no_rate_means_no_limit() {
+        let policy = MotionPolicy {
+            max_step: None,
+            rate: None,
+            burst: 1.0,
+        };
+        let mut bucket = TokenBucket::new(policy.burst);
+        for _ in 0..1000 {
+            assert!(check_motion(&policy, &mut bucket, &motion(1, 0)).is_ok());
+        }
+    }
+
+    #[test]
+    fn extreme_deltas_are_measured_without_overflow() {
+        let policy = MotionPolicy {
+            max_step: Some(10),
+            rate: None,
+            burst: 1.0,
+        };
+        let mut bucket = TokenBucket::new(policy.burst);
+        let extremes =
+            [motion(i32::MIN, 0), motion(0, i32::MIN), motion(i32::MAX, 0)];
+        for delta in extremes {
+            let refused = check_motion(&policy, &mut bucket, &delta);
+            let (code, reason) = refused.unwrap_err();
+            assert_eq!(code, ErrorCode::InvalidMotion);
+            assert!(reason.contains("pixels"), "{}", reason);
+        }
+        let unlimited = MotionPolicy {
+            max_step: None,
+            ..policy
+        };
+        let extreme = motion(i32::MIN, i32::MAX);
+        assert!(check_motion(&unlimited, &mut bucket, &extreme).is_ok());
+    }
+}
@@ -1,61 +1,286 @@
+mod collision;
+mod config;
+mod errors;
+#[cfg(unix)]
+mod event_loop;
+mod heartbeat;
+mod limits;
+mod outbox;
+mod rooms;
+mod session;
+mod shutdown;
+mod simulation;
+mod snapshot;
+mod udp;
+mod websocket;
+
+use game_protocol::{
+    check_name, ChatLine, ClientMessage, ErrorCode, Frame, FrameDecoder,
+    Framing, HeartbeatPolicy, Hello, Image, ImageHash, Motion, Point,
+    ServerMessage, SessionOffer, UdpOffer, Welcome, BINARY_FRAMES,
+    PROTOCOL_VERSION, UDP_CHANNEL,
+};
+use collision::CollisionMode;
+use config::ServerConfig;
+use errors::ErrorPolicy;
+use heartbeat::Heartbeat;
+use limits::{ChatPolicy, ImagePolicy, MotionPolicy, TokenBucket};
+use outbox::Outbox;
 use rand::Rng;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use rooms::Room;
+use snapshot::Members;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{
-    io::{BufRead, BufReader, Write},
-    net::{Ipv4Addr, TcpListener, TcpStream},
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
-struct Point {
-    x: i32,
-    y: i32,
-}
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-}
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Image {
-    width: usize,
-    height: usize,
-    pixels: Vec<Color>,
-}
+// Fonctionnalités optionnelles que le serveur sait négocier (`UDP_CHANNEL`
+// seulement si le canal UDP est ouvert)
+const SERVER_CAPABILITIES: &[&str] = &[BINARY_FRAMES, UDP_CHANNEL];
+
+// Délai accordé à une nouvelle connexion pour envoyer `hello` (et, pour un
+// client web, sa requête HTTP avant cela)
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Octets en attente tolérés avant `hello`, bien plus court qu'une trame
+// quelconque (`MAX_FRAME_LEN`)
+const MAX_HELLO_LEN: usize = 4 * 1024;
 
 #[derive(Debug)]
 struct ClientInfo {
     position: Point,
-    image: Image,
-    stream: TcpStream,
+    image: Option<ImageHash>, // Avatar du client, absent tant qu'il n'a pas été envoyé
+    name: Option<String>, // Pseudonyme unique parmi les joueurs, s'il en a un
+    room: String, // Clé du salon du joueur dans `ServerState::rooms`
+    outbox: Option<Outbox>, // Messages en attente d'envoi, absente si le client est déconnecté
+    framing: Framing, // Format des messages négocié lors de la poignée de main
+    websocket: bool, // Client web : chaque message part dans un message WebSocket
+    acked: Option<u64>, // Dernier instantané acquitté par le client
+    inputs: VecDeque<Motion>, // Déplacements en attente du prochain pas de simulation
+    last_input: u32, // Numéro du dernier déplacement appliqué
+    udp_token: u64, // Jeton attendu dans `udp_hello`
+    udp: Option<SocketAddr>, // Adresse UDP du client une fois le canal ouvert
+    members: Option<(u64, Members)>, // Dernier état sans positions envoyé par TCP aux clients UDP
+    last_seen: Instant, // Dernier message reçu par TCP, pour détecter les connexions mortes
+    session: String, // Jeton permettant de reprendre la session après une coupure
+    connection: u64, // Connexion actuelle, les messages d'une ancienne connexion sont ignorés
+    detached: Option<Instant>, // Début de la coupure, si le client attend d'être repris
+    invalid: VecDeque<Instant>, // Messages invalides récents (voir `errors::invalid`)
+    violations: HashMap<ErrorCode, u32>, // Messages refusés depuis l'arrivée du joueur, par motif
+    motions: TokenBucket, // Déplacements que le client peut encore envoyer sans attendre
+    chats: TokenBucket, // Messages de discussion que le client peut encore envoyer
+}
+
+impl ClientInfo {
+    // Déposer un message dans la file d'envoi du client, dans le format
+    // négocié avec lui ; une erreur signifie que le client ne suit plus.
+    // Pendant une coupure, les messages sont perdus : le client recevra
+    // l'état complet en reprenant sa session
+    fn send(
+        &mut self,
+        message: &ServerMessage,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(outbox) = self.outbox.as_mut() else {
+            return Ok(());
+        };
+        let bytes = encode_message(self.websocket, self.framing, message)?;
+        outbox.push(bytes)?;
+        Ok(())
+    }
+
+    // Mettre un déplacement en attente du prochain pas ; par UDP, un
+    // déplacement plus ancien que le dernier reçu arrive en retard et est
+    // ignoré
+    fn push_input(&mut self, motion: Motion) {
+        let latest = self
+            .inputs
+            .back()
+            .map_or(self.last_input, |motion| motion.seq);
+        if motion.seq <= latest {
+            println!(
+                "Déplacement {} en retard ignoré (dernier : {})",
+                motion.seq, latest
+            );
+            return;
+        }
+        self.inputs.push_back(motion);
+    }
 }
 #[derive(Debug)]
 struct ServerState {
     next_id: u32, // Pour générer des identifiants uniques
     clients: HashMap<u32, ClientInfo>, // Associe chaque ID à son ClientInfo
     positions: HashMap<u32, Point>, // Positions de tous les clients
-    images: HashMap<u32, Vec<u8>>, // Images des clients (format binaire)
+    images: HashMap<ImageHash, Image>, // Avatars des clients, indexés par empreinte
+    rooms: HashMap<String, Room>, // Salons ouverts, indexés par `rooms::key`
+    max_rooms: Option<usize>, // Salons ouverts en plus de l'accueil (`None` : sans limite)
+    last_seq: u64, // Numéro du dernier instantané, tous salons confondus
+    started: Instant, // Origine de l'horloge des instantanés
+    udp: Option<UdpSocket>, // Canal UDP pour les positions, s'il est activé
+    heartbeat: Option<Heartbeat>, // Surveillance des connexions inactives
+    session_grace: Option<Duration>, // Délai de reprise après une coupure (`None` : aucun)
+    next_connection: u64, // Pour distinguer les connexions successives d'un même client
+    closing: bool, // Arrêt en cours : les nouveaux clients sont refusés
+    error_policy: Option<ErrorPolicy>, // Limite de messages invalides (`None` : aucune)
+    motion_policy: MotionPolicy, // Limites imposées aux déplacements
+    collision: CollisionMode, // Collisions entre les avatars d'un même salon
+    image_policy: ImagePolicy, // Limites imposées aux avatars
+    chat_policy: ChatPolicy, // Limite de débit des messages de discussion
+}
+
+impl ServerState {
+    // État de départ : le salon d'accueil vide, limites tirées de la
+    // configuration
+    fn new(config: &ServerConfig, udp: Option<UdpSocket>) -> Self {
+        ServerState {
+            next_id: 1,
+            clients: HashMap::new(),
+            positions: HashMap::new(),
+            images: HashMap::new(),
+            rooms: HashMap::from([(
+                rooms::LOBBY.to_string(),
+                Room::new(
+                    rooms::LOBBY,
+                    config.world_width,
+                    config.world_height,
+                ),
+            )]),
+            max_rooms: config.max_rooms,
+            last_seq: 0,
+            started: Instant::now(),
+            udp,
+            heartbeat: config.heartbeat.map(|interval| {
+                Heartbeat::new(HeartbeatPolicy {
+                    interval_ms: interval.as_millis() as u64,
+                    misses: config.heartbeat_misses,
+                })
+            }),
+            session_grace: config.session_grace,
+            next_connection: 1,
+            closing: false,
+            error_policy: config.max_invalid.map(|max| ErrorPolicy {
+                max,
+                window: config.invalid_window,
+            }),
+            collision: config.collision,
+            motion_policy: MotionPolicy {
+                max_step: config.max_step,
+                rate: config.motion_rate,
+                burst: config.motion_burst,
+            },
+            image_policy: ImagePolicy {
+                max_side: config.max_avatar_side,
+                max_bytes: config.max_avatar_bytes,
+            },
+            chat_policy: ChatPolicy {
+                rate: config.chat_rate,
+                burst: config.chat_burst,
+            },
+        }
+    }
 }
 
 type SharedServerState = Arc<Mutex<ServerState>>;
 
+// Encoder un message dans le format négocié, enveloppé dans un message
+// WebSocket pour les clients web
+fn encode_message(
+    websocket: bool,
+    framing: Framing,
+    message: &ServerMessage,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bytes = message.encode(framing)?;
+    if websocket {
+        Ok(websocket::message_frame(framing, &bytes))
+    } else {
+        Ok(bytes)
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let tcp_port = 5555;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = ServerConfig::from_args(&args)?;
+
+    let tcp_port = config.port;
     let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, tcp_port))?;
     println!(
-        "Serveur TCP en attente de connexions sur le port {}",
-        tcp_port
+        "Serveur TCP en attente de connexions sur le port {} ({} pas/s)",
+        tcp_port, config.tick_rate
     );
 
+    let udp = match config.udp_port {
+        Some(port) => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+            println!("Canal UDP ouvert sur le port {}", port);
+            Some(socket)
+        }
+        None => None,
+    };
+
+    let udp_clone = udp.as_ref().map(UdpSocket::try_clone).transpose()?;
+    let state = ServerState::new(&config, udp_clone);
+
+    // Arrêt propre sur SIGINT, SIGTERM ou commande `shutdown`
+    shutdown::install_signal_handlers();
+    shutdown::listen_console();
+
+    if config.event_loop {
+        return run_event_loop(listener, udp, state, config.tick_rate);
+    }
+
     // État partagé entre tous les threads
-    let state: SharedServerState = Arc::new(Mutex::new(ServerState {
-        next_id: 1,
-        clients: HashMap::new(),
-        positions: HashMap::new(),
-        images: HashMap::new(),
-    }));
+    let state: SharedServerState = Arc::new(Mutex::new(state));
+
+    // Réception des datagrammes UDP
+    if let Some(socket) = udp {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || udp::run(socket, state));
+    }
+
+    // Simulation du monde à fréquence fixe
+    {
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || simulation::run(state, config.tick_rate));
+    }
+
+    // Passerelle WebSocket pour les clients web
+    if let Some(ws_port) = config.ws_port {
+        let ws_listener =
+            TcpListener::bind((Ipv4Addr::UNSPECIFIED, ws_port))?;
+        println!("Passerelle WebSocket sur le port {}", ws_port);
+        let state = Arc::clone(&state);
+        std::thread::spawn(move || {
+            for incoming in ws_listener.incoming() {
+                match incoming {
+                    Ok(mut stream) => {
+                        let state_clone = Arc::clone(&state);
+                        std::thread::spawn(move || {
+                            // Sans mise à niveau, la réponse HTTP est déjà
+                            // partie et la connexion se termine
+                            let result = websocket::accept(&mut stream)
+                                .and_then(|upgraded| {
+                                    if !upgraded {
+                                        return Ok(());
+                                    }
+                                    handle_connection(stream, state_clone, true)
+                                });
+                            if let Err(e) = result {
+                                eprintln!("Erreur WebSocket : {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("Erreur de connexion WebSocket : {}", e)
+                    }
+                }
+            }
+        });
+    }
 
     // Boucle principale du serveur
     for incoming in listener.incoming() {
@@ -63,7 +288,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Ok(stream) => {
                 let state_clone = Arc::clone(&state);
                 std::thread::spawn(move || {
-                    if let Err(e) = handle_connection(stream, state_clone) {
+                    let result = handle_connection(stream, state_clone, false);
+                    if let Err(e) = result {
                         eprintln!("Erreur : {}", e);
                     }
                 });
@@ -75,137 +301,537 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Un seul thread pour toutes les connexions : l'état n'est pas partagé
+#[cfg(unix)]
+fn run_event_loop(
+    listener: TcpListener,
+    udp: Option<UdpSocket>,
+    state: ServerState,
+    tick_rate: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    event_loop::run(listener, udp, state, tick_rate)
+}
+
+#[cfg(not(unix))]
+fn run_event_loop(
+    _listener: TcpListener,
+    _udp: Option<UdpSocket>,
+    _state: ServerState,
+    _tick_rate: f64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("--event-loop n'est disponible que sous Unix".into())
+}
+
 fn handle_connection(
     stream: TcpStream,
     state: SharedServerState,
+    websocket: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let output = stream.try_clone()?;
-    let mut input = BufReader::new(stream);
-
-    // Enregistrement initial : génération d'un identifiant unique
-    let client_id;
-    {
-        let mut state = state.lock().unwrap();
+    let registered = Rc::new(Cell::new(None));
+    // Les messages WebSocket sont déballés avant d'arriver au décodeur
+    let mut input: Box<dyn Read> = if websocket {
+        let pongs = Pongs {
+            output: stream.try_clone()?,
+            state: Arc::clone(&state),
+            registered: Rc::clone(&registered),
+        };
+        Box::new(websocket::Reader::new(stream, pongs))
+    } else {
+        Box::new(stream)
+    };
+    let mut decoder = FrameDecoder::new(Framing::Lines);
 
-        client_id = state.next_id;
-        state.next_id += 1;
+    // Poignée de main : le client doit d'abord envoyer `hello`
+    let hello =
+        match handle_hello(&mut input, &mut decoder, &output, websocket)? {
+            Some(hello) => hello,
+            None => return Ok(()),
+        };
 
-        // Ajouter le client à l'état global avec des champs vides pour l'image et la position
-        state.clients.insert(
-            client_id,
-            ClientInfo {
-                position: Point { x: 0, y: 0 },
-                image: Image {
-                    width: 0,
-                    height: 0,
-                    pixels: Vec::new(),
-                },
-                stream: output.try_clone()?,
-            },
-        );
+    let mut guard = state.lock().unwrap();
+    if let Err(reason) = admit(&guard, &hello) {
+        drop(guard);
+        reject(&output, websocket, reason)?;
+        return Ok(());
     }
-
-    println!("Client {} connecté, en attente de données.", client_id);
+    let (client_id, connection, framing) = register_client(
+        &mut guard,
+        hello,
+        websocket,
+        |client_id| Outbox::new(client_id, &output),
+    )?;
+    drop(guard);
+    // Le client est accueilli : il peut désormais rester silencieux, le
+    // battement de cœur se charge des connexions mortes
+    output.set_read_timeout(None)?;
+    registered.set(Some((client_id, connection)));
+    decoder.set_framing(framing);
 
     // Boucle principale : surveiller les messages du client
     loop {
-        let mut request = String::new();
-        let r = input.read_line(&mut request)?;
-        if r == 0 {
-            // Fin de communication
-            handle_disconnect(client_id, &state)?;
+        let frame = match decoder.read_frame(&mut input) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                // Fin de communication
+                let mut state = state.lock().unwrap();
+                handle_disconnect(client_id, connection, &mut state);
+                break;
+            }
+            Err(e) => {
+                // Connexion coupée ou flux illisible : ne pas laisser le
+                // client recevoir les instantanés sur cette connexion
+                let mut state = state.lock().unwrap();
+                handle_disconnect(client_id, connection, &mut state);
+                return Err(e.into());
+            }
+        };
+
+        let mut state = state.lock().unwrap();
+        // Client évincé, parti ou repris sur une autre connexion
+        if !is_current(&state, client_id, connection) {
             break;
         }
+        handle_message(client_id, &frame, &mut state)?;
+    }
 
-        if let Some(input) = request.strip_prefix("motion ") {
-            // Demande de déplacement
-            match serde_json::from_str::<Point>(input.trim()) {
-                Ok(delta) => {
-                    let new_position =
-                        handle_motion(client_id, delta, &state)?;
-                    println!(
-                        "Client {} déplacé vers la nouvelle position {:?}",
-                        client_id, new_position
-                    );
-                }
-                Err(e) => {
-                    eprintln!(
-                        "Message de déplacement mal formaté {} : {}",
-                        input, e
-                    );
-                }
+    Ok(())
+}
+
+// Destination des pongs d'un client WebSocket : la connexion tant que le
+// client n'est pas enregistré, puis sa file d'envoi, pour ne pas couper un
+// message en cours d'écriture par le thread d'envoi
+struct Pongs {
+    output: TcpStream,
+    state: SharedServerState,
+    registered: Rc<Cell<Option<(u32, u64)>>>, // Client et connexion, une fois enregistré
+}
+
+impl Write for Pongs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some((client_id, connection)) = self.registered.get() else {
+            return self.output.write(buf);
+        };
+        let mut state = self.state.lock().unwrap();
+        if !is_current(&state, client_id, connection) {
+            return Ok(buf.len());
+        }
+        if let Some(outbox) = state
+            .clients
+            .get_mut(&client_id)
+            .and_then(|client| client.outbox.as_mut())
+        {
+            // File pleine : le client sera évincé à la prochaine diffusion
+            let _ = outbox.push(buf.to_vec());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// Attendre `hello` au plus `HELLO_TIMEOUT` et `MAX_HELLO_LEN` octets,
+// comme le cœur à boucle d'événements, pour qu'une connexion muette ou qui
+// n'envoie que du bruit n'occupe pas un thread indéfiniment
+fn handle_hello(
+    input: &mut impl Read,
+    decoder: &mut FrameDecoder,
+    output: &TcpStream,
+    websocket: bool,
+) -> Result<Option<Hello>, Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + HELLO_TIMEOUT;
+    let mut chunk = [0u8; 4096];
+    let frame = loop {
+        if let Some(frame) = decoder.next_frame()? {
+            break frame;
+        }
+        if decoder.pending() > MAX_HELLO_LEN {
+            eprintln!(
+                "Connexion fermée : plus de {} octets avant 'hello'",
+                MAX_HELLO_LEN
+            );
+            return Ok(None);
+        }
+        // Le délai vaut pour le socket, partagé par `input` et `output`
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            eprintln!(
+                "Connexion fermée : pas de 'hello' après {:?}",
+                HELLO_TIMEOUT
+            );
+            return Ok(None);
+        }
+        output.set_read_timeout(Some(left))?;
+        match input.read(&mut chunk) {
+            // Connexion fermée avant la poignée de main
+            Ok(0) => return Ok(None),
+            Ok(r) => decoder.extend(&chunk[..r]),
+            // Délai écoulé : signalé au prochain tour
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    match check_hello(&frame) {
+        Ok(hello) => Ok(Some(hello)),
+        Err(reason) => {
+            reject(output, websocket, reason)?;
+            Ok(None)
+        }
+    }
+}
+
+// Lecture interrompue par le délai du socket (`WouldBlock` sous Unix,
+// `TimedOut` sous Windows)
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+// Vérifier le premier message d'un client ; l'erreur contient la raison du
+// refus
+fn check_hello(frame: &Frame) -> Result<Hello, String> {
+    match ClientMessage::decode(frame) {
+        Ok(ClientMessage::Hello(hello)) if hello.version == PROTOCOL_VERSION => {
+            Ok(hello)
+        }
+        Ok(ClientMessage::Hello(hello)) => Err(format!(
+            "Version de protocole {} non supportée (attendue : {})",
+            hello.version, PROTOCOL_VERSION
+        )),
+        Ok(_) => Err("Le premier message doit être 'hello'".to_string()),
+        Err(e) => Err(format!("Poignée de main invalide : {}", e)),
+    }
+}
+
+// Vérifier qu'un client peut rejoindre le monde ; l'erreur contient la
+// raison du refus
+fn admit(state: &ServerState, hello: &Hello) -> Result<(), String> {
+    // Le serveur à threads ne peut plus fermer son socket d'écoute, bloqué
+    // dans `accept` : les clients arrivés pendant l'arrêt sont refusés ici
+    if state.closing {
+        return Err("Serveur en cours d'arrêt".to_string());
+    }
+    let Some(name) = &hello.name else {
+        return Ok(());
+    };
+    check_name(name).map_err(|e| format!("Pseudonyme refusé : {}", e))?;
+
+    // Un joueur qui reprend sa session garde son pseudonyme
+    let resumed = hello
+        .resume
+        .as_deref()
+        .and_then(|token| session::find(state, token));
+    let lower = name.to_lowercase();
+    let taken = state.clients.iter().any(|(&id, client)| {
+        Some(id) != resumed
+            && client
+                .name
+                .as_ref()
+                .is_some_and(|other| other.to_lowercase() == lower)
+    });
+    if taken {
+        return Err(format!("Pseudonyme « {} » déjà utilisé", name));
+    }
+    Ok(())
+}
+
+// Refuser proprement le client avant de fermer la connexion
+fn reject(
+    mut output: &TcpStream,
+    websocket: bool,
+    reason: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!("Client refusé : {}", reason);
+    let rejected = ServerMessage::Rejected(reason);
+    output.write_all(&encode_message(websocket, Framing::Lines, &rejected)?)?;
+    output.flush()?;
+    Ok(())
+}
+
+// Enregistrement d'un client qui a réussi la poignée de main : nouvel
+// identifiant ou reprise de sa session, `welcome` puis état complet du
+// monde. Retourne l'identifiant, la connexion et le format négocié
+fn register_client(
+    state: &mut ServerState,
+    hello: Hello,
+    websocket: bool,
+    open_outbox: impl FnOnce(u32) -> Result<Outbox, Box<dyn std::error::Error>>,
+) -> Result<(u32, u64, Framing), Box<dyn std::error::Error>> {
+    let resumed = hello
+        .resume
+        .as_deref()
+        .and_then(|token| session::find(state, token));
+    let client_id = match resumed {
+        Some(client_id) => client_id,
+        None => {
+            let client_id = state.next_id;
+            state.next_id += 1;
+            client_id
+        }
+    };
+    let connection = state.next_connection;
+    state.next_connection += 1;
+
+    // Ne retenir que les fonctionnalités connues des deux côtés
+    let udp_port = match &state.udp {
+        Some(socket) => Some(socket.local_addr()?.port()),
+        None => None,
+    };
+    let capabilities: Vec<String> = hello
+        .capabilities
+        .into_iter()
+        .filter(|c| SERVER_CAPABILITIES.contains(&c.as_str()))
+        .filter(|c| c != UDP_CHANNEL || udp_port.is_some())
+        .collect();
+    let framing = Framing::from_capabilities(&capabilities);
+    let udp_token: u64 = rand::thread_rng().gen();
+    let udp = udp_port
+        .filter(|_| capabilities.iter().any(|c| c == UDP_CHANNEL))
+        .map(|port| UdpOffer {
+            port,
+            token: udp_token,
+        });
+    let (token, room) = match resumed.and_then(|id| state.clients.get(&id)) {
+        Some(client) => (client.session.clone(), client.room.clone()),
+        None => (session::new_token(), rooms::LOBBY.to_string()),
+    };
+    let world = &state.rooms[&room];
+
+    // Communiquer au client son identifiant et les dimensions du monde de
+    // son salon (toujours en lignes de texte, le format négocié s'applique
+    // ensuite)
+    let welcome = ServerMessage::Welcome(Welcome {
+        client_id,
+        world_width: world.width,
+        world_height: world.height,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        capabilities,
+        udp,
+        heartbeat: state.heartbeat.as_ref().map(Heartbeat::policy),
+        session: state.session_grace.map(|grace| SessionOffer {
+            token: token.clone(),
+            grace_ms: grace.as_millis() as u64,
+        }),
+        resumed: resumed.is_some(),
+        room: Some(world.name.clone()),
+    });
+    let mut outbox = open_outbox(client_id)?;
+    outbox.push(encode_message(websocket, Framing::Lines, &welcome)?)?;
+
+    match state.clients.get_mut(&client_id) {
+        Some(client) => {
+            // Reprise : la position et l'avatar sont conservés, les autres
+            // joueurs ne voient ni départ ni arrivée
+            if let Some(mut previous) = client.outbox.replace(outbox) {
+                // Ancienne connexion pas encore détectée comme coupée
+                let kicked = ServerMessage::Kicked(
+                    "session reprise sur une autre connexion".to_string(),
+                );
+                previous.close(
+                    encode_message(client.websocket, client.framing, &kicked)
+                        .ok(),
+                );
             }
+            client.framing = framing;
+            client.websocket = websocket;
+            client.acked = None;
+            client.inputs.clear();
+            client.last_input = 0; // Le client renumérote ses déplacements
+            client.udp_token = udp_token;
+            client.udp = None;
+            client.members = None;
+            client.last_seen = Instant::now();
+            client.connection = connection;
+            client.detached = None;
         }
-        if let Some(input) = request.strip_prefix("image ") {
-            // Récupération de l'image
-            match serde_json::from_str::<Image>(input.trim()) {
-                Ok(image) => {
-                    handle_image(client_id, image, &state)?;
+        None => {
+            // Nouveau client, sans image et avec une position par défaut
+            state.clients.insert(
+                client_id,
+                ClientInfo {
+                    position: Point { x: 0, y: 0 },
+                    image: None,
+                    name: hello.name.clone(),
+                    room: room.clone(),
+                    outbox: Some(outbox),
+                    framing,
+                    websocket,
+                    acked: None,
+                    inputs: VecDeque::new(),
+                    last_input: 0,
+                    udp_token,
+                    udp: None,
+                    members: None,
+                    last_seen: Instant::now(),
+                    session: token,
+                    connection,
+                    detached: None,
+                    invalid: VecDeque::new(),
+                    violations: HashMap::new(),
+                    motions: TokenBucket::new(state.motion_policy.burst),
+                    chats: TokenBucket::new(state.chat_policy.burst),
+                },
+            );
+        }
+    }
+
+    // Envoyer l'état complet du salon au client
+    let snapshot = state.rooms[&room].snapshots.snapshot_for(None);
+    if let (Some(snapshot), Some(client)) =
+        (snapshot, state.clients.get_mut(&client_id))
+    {
+        if let Err(e) = client.send(&ServerMessage::Snapshot(snapshot)) {
+            evict(state, client_id, &e.to_string());
+        }
+    }
+
+    if resumed.is_some() {
+        println!("Client {} : session reprise.", client_id);
+    } else {
+        println!(
+            "Client {}{} connecté (protocole v{}), en attente de données.",
+            client_id,
+            hello.name.map_or(String::new(), |name| format!(" ({})", name)),
+            hello.version
+        );
+    }
+    Ok((client_id, connection, framing))
+}
+
+// La connexion `connection` est-elle toujours celle du client ?
+fn is_current(state: &ServerState, client_id: u32, connection: u64) -> bool {
+    state
+        .clients
+        .get(&client_id)
+        .is_some_and(|client| client.connection == connection)
+}
+
+// Traiter un message d'un client enregistré
+fn handle_message(
+    client_id: u32,
+    frame: &Frame,
+    state: &mut ServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Tout message, même invalide, montre que la connexion est vivante
+    if let Some(client) = state.clients.get_mut(&client_id) {
+        client.last_seen = Instant::now();
+    }
+
+    match ClientMessage::decode(frame) {
+        Ok(ClientMessage::Motion(motion)) => {
+            // Demande de déplacement, appliquée au prochain pas
+            handle_motion(client_id, motion, state)?;
+        }
+        Ok(ClientMessage::Image(image)) => {
+            // Récupération de l'image, si elle respecte les limites
+            match limits::check_image(&state.image_policy, &image) {
+                Ok(()) => {
+                    handle_image(client_id, image, state)?;
                     println!("Client {} registered", client_id);
                 }
-                Err(e) => {
-                    eprintln!(
-                        "Erreur de désérialisation JSON dans '{}': {}",
-                        input, e
-                    );
-                }
+                Err(reason) => errors::invalid(
+                    state,
+                    client_id,
+                    ErrorCode::InvalidImage,
+                    &frame.verb,
+                    reason,
+                ),
             }
-        } else {
-            println!(
-                "Message reçu du client {} : {}",
+        }
+        Ok(ClientMessage::GetImage(hash)) => {
+            handle_get_image(client_id, &hash, state)?;
+        }
+        Ok(ClientMessage::Ack(seq)) => {
+            handle_ack(client_id, seq, state)?;
+        }
+        Ok(ClientMessage::UdpHello(_)) => {
+            let message = "'udp_hello' s'envoie par datagramme UDP";
+            errors::invalid(
+                state,
+                client_id,
+                ErrorCode::Unexpected,
+                &frame.verb,
+                message.to_string(),
+            );
+        }
+        Ok(ClientMessage::Ping(seq)) => {
+            handle_ping(client_id, seq, state)?;
+        }
+        Ok(ClientMessage::Pong(_)) => {}
+        Ok(ClientMessage::Leave) => {
+            handle_leave(client_id, state);
+        }
+        Ok(ClientMessage::Chat(text)) => {
+            handle_chat(client_id, &text, state)?;
+        }
+        Ok(ClientMessage::ListRooms) => rooms::list(state, client_id),
+        Ok(ClientMessage::CreateRoom(request)) => {
+            rooms::create(state, client_id, request);
+        }
+        Ok(ClientMessage::JoinRoom(name)) => {
+            rooms::join_by_name(state, client_id, &name);
+        }
+        Ok(ClientMessage::LeaveRoom) => rooms::leave(state, client_id),
+        Ok(ClientMessage::Hello(_)) => {
+            errors::invalid(
+                state,
                 client_id,
-                request.trim()
+                ErrorCode::Unexpected,
+                &frame.verb,
+                "poignée de main déjà effectuée".to_string(),
             );
         }
+        Err(e) => {
+            let code = if ClientMessage::is_known(&frame.verb) {
+                ErrorCode::Malformed
+            } else {
+                ErrorCode::UnknownVerb
+            };
+            errors::invalid(state, client_id, code, &frame.verb, e.to_string());
+        }
     }
-
     Ok(())
 }
 
 fn handle_motion(
     client_id: u32,
-    delta: Point,
-    state: &SharedServerState,
-) -> Result<Point, Box<dyn std::error::Error>> {
-    let mut state = state.lock().unwrap();
-
+    motion: Motion,
+    state: &mut ServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = state.motion_policy;
     if let Some(client) = state.clients.get_mut(&client_id) {
-        client.position.x += delta.x;
-        client.position.y += delta.y;
-
-        // Limiter la position aux dimensions autorisées (par exemple, 800x600)
-        client.position.x = client.position.x.clamp(0, 800);
-        client.position.y = client.position.y.clamp(0, 600);
-
-        // Nouvelle position à envoyer
-        let new_position = client.position;
-
-        // Envoyer la nouvelle position au client
-        let reply = serde_json::to_string(&(client_id, new_position))?;
-        client
-            .stream
-            .write_all(format!("position {}\n", reply).as_bytes())?;
-        client.stream.flush()?;
-
-        // Envoyer la nouvelle position à tous les autres clients
-        let position_update =
-            serde_json::to_string(&(client_id, new_position))?;
-
-        for (&other_id, other_client) in &mut state.clients {
-            if other_id != client_id {
-                if let Err(e) = other_client.stream.write_all(
-                    format!("position {}\n", position_update).as_bytes(),
-                ) {
-                    eprintln!(
-                        "Erreur lors de l'envoi de la mise à jour au client {} : {}",
-                        other_id, e
-                    );
+        let checked =
+            limits::check_motion(&policy, &mut client.motions, &motion);
+        match checked {
+            // Le déplacement sera appliqué et diffusé par la boucle de
+            // simulation
+            Ok(()) => client.push_input(motion),
+            Err((code, reason)) => {
+                // Consommé sans effet : le client voit son numéro acquitté
+                // et corrige sa position anticipée
+                client.push_input(Motion {
+                    delta: Point { x: 0, y: 0 },
+                    ..motion
+                });
+                // Un client un peu trop rapide n'est pas fautif : ses
+                // déplacements en trop ne comptent pas pour l'éviction, et
+                // il n'est prévenu qu'une fois tant que son seau est vide
+                if code == ErrorCode::RateLimited {
+                    *client.violations.entry(code).or_default() += 1;
+                    if client.motions.first_refusal() {
+                        errors::reply(state, client_id, code, "motion", reason);
+                    }
+                    return Ok(());
                 }
+                errors::invalid(state, client_id, code, "motion", reason);
             }
         }
-
-        // Retourner la nouvelle position
-        Ok(new_position)
+        Ok(())
     } else {
         // Retourner une erreur si le client n'existe pas
         Err(format!("Client {} introuvable", client_id).into())
@@ -215,71 +841,30 @@ fn handle_motion(
 fn handle_image(
     client_id: u32,
     image: Image,
-    state: &SharedServerState,
+    state: &mut ServerState,
 ) -> Result<(u32, Point), Box<dyn std::error::Error>> {
-    // Verrouiller l'état partagé
-    let mut state = state.lock().unwrap();
+    let hash = image.hash();
 
     // Vérifier si le client existe dans l'état
-    let position = if let Some(client) = state.clients.get_mut(&client_id) {
-        let mut rng = rand::thread_rng();
-        let new_position = Point {
-            x: rng.gen_range(0..800),
-            y: rng.gen_range(0..600),
-        };
+    let (position, previous) =
+        if let Some(client) = state.clients.get_mut(&client_id) {
+            let room = &state.rooms[&client.room];
+            let new_position = room.spawn_point(Some(&image));
 
-        // Mettre à jour la position et l'image du client dans l'état
-        client.position = new_position;
-        client.image = image.clone();
+            // Mettre à jour la position et l'image du client dans l'état
+            client.position = new_position;
+            let previous = client.image.replace(hash.clone());
 
-        // Envoyer la paire (id, position) au client
-        let reply =
-            serde_json::to_string(&(client_id, image.clone(), new_position))?;
-        client
-            .stream
-            .write_all(format!("image {}\n", reply).as_bytes())?;
-        client.stream.flush()?;
-
-        new_position
-    } else {
-        // Retourner une erreur si le client n'existe pas
-        return Err(format!("Client {} introuvable", client_id).into());
-    };
-
-    // Créer la liste des clients avec leur id, image, et position à envoyer au client courant
-    let all_clients_data: Vec<(u32, Image, Point)> = state
-        .clients
-        .iter()
-        .map(|(&id, client)| (id, client.image.clone(), client.position))
-        .collect();
-
-    let all_clients_message = serde_json::to_string(&all_clients_data)?;
-
-    // Envoyer la liste des données des clients au client actuel et à tous les autres clients
-    let clients_snapshot: Vec<_> = state.clients.iter().collect();
-
-    for (&other_id, client) in clients_snapshot {
-        if let Ok(mut stream) = client.stream.try_clone() {
-            if let Err(e) = stream.write_all(
-                format!("all_clients {}\n", all_clients_message).as_bytes(),
-            ) {
-                eprintln!(
-                    "Erreur lors de l'envoi de la mise à jour au client {} : {}",
-                    other_id, e
-                );
-            }
-            if let Err(e) = stream.flush() {
-                eprintln!(
-                    "Erreur lors de la tentative de flush du flux pour le client {} : {}",
-                    other_id, e
-                );
-            }
+            (new_position, previous)
         } else {
-            eprintln!(
-                "Erreur lors de la tentative de clonage du flux pour le client {}",
-                other_id
-            );
-        }
+            // Retourner une erreur si le client n'existe pas
+            return Err(format!("Client {} introuvable", client_id).into());
+        };
+
+    // Conserver une seule copie de chaque image, partagée par empreinte
+    state.images.entry(hash).or_insert(image);
+    if let Some(previous) = previous {
+        release_image(state, &previous);
     }
 
     println!(
@@ -291,27 +876,269 @@ fn handle_image(
     Ok((client_id, position))
 }
 
-fn handle_disconnect(
+fn handle_get_image(
     client_id: u32,
-    state: &SharedServerState,
+    hash: &ImageHash,
+    state: &mut ServerState,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut state = state.lock().unwrap();
-
-    if state.clients.remove(&client_id).is_some() {
-        state.positions.remove(&client_id);
-        state.images.remove(&client_id);
-
-        // Diffuser l'identifiant du client partant
-        let message = format!("client_left {}\n", client_id);
-        for (&other_id, other_client) in &mut state.clients {
-            if let Err(e) = other_client.stream.write_all(message.as_bytes())
-            {
-                eprintln!("Erreur d'envoi au client {} : {}", other_id, e);
+    let client = state
+        .clients
+        .get_mut(&client_id)
+        .ok_or_else(|| format!("Client {} introuvable", client_id))?;
+
+    // Envoyer le contenu de l'image demandée s'il est connu
+    match state.images.get(hash) {
+        Some(image) => {
+            let sent = client.send(&ServerMessage::ImageData {
+                hash: hash.clone(),
+                image: image.clone(),
+            });
+            match sent {
+                Ok(()) => {
+                    println!("Image {} envoyée au client {}", hash, client_id)
+                }
+                Err(e) => evict(state, client_id, &e.to_string()),
             }
         }
+        None => {
+            // L'image a pu être oubliée depuis l'instantané qui la citait :
+            // ce n'est pas une faute du client
+            errors::reply(
+                state,
+                client_id,
+                ErrorCode::UnknownImage,
+                "get_image",
+                format!("image {} inconnue", hash),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_ack(
+    client_id: u32,
+    seq: u64,
+    state: &mut ServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let latest = state.last_seq;
+
+    let client = state
+        .clients
+        .get_mut(&client_id)
+        .ok_or_else(|| format!("Client {} introuvable", client_id))?;
 
-        println!("Client {} déconnecté et supprimé.", client_id);
+    // Ignorer les acquittements d'instantanés jamais envoyés ou dépassés
+    if seq <= latest && client.acked.is_none_or(|acked| seq > acked) {
+        client.acked = Some(seq);
     }
 
     Ok(())
 }
+
+fn handle_ping(
+    client_id: u32,
+    seq: u64,
+    state: &mut ServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = state
+        .clients
+        .get_mut(&client_id)
+        .ok_or_else(|| format!("Client {} introuvable", client_id))?;
+
+    if let Err(e) = client.send(&ServerMessage::Pong(seq)) {
+        evict(state, client_id, &e.to_string());
+    }
+
+    Ok(())
+}
+
+// Relayer un message de discussion aux joueurs connectés du salon de son
+// auteur, lui compris
+fn handle_chat(
+    client_id: u32,
+    text: &str,
+    state: &mut ServerState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = state.chat_policy;
+    let client = state
+        .clients
+        .get_mut(&client_id)
+        .ok_or_else(|| format!("Client {} introuvable", client_id))?;
+
+    if let Err((code, reason)) =
+        limits::check_chat_message(&policy, &mut client.chats, text)
+    {
+        errors::invalid(state, client_id, code, "chat", reason);
+        return Ok(());
+    }
+
+    let line = ChatLine {
+        from: client_id,
+        name: client.name.clone(),
+        text: text.trim().to_string(),
+    };
+    match &line.name {
+        Some(name) => println!("[{}] {} : {}", client_id, name, line.text),
+        None => println!("[{}] {}", client_id, line.text),
+    }
+
+    let room = client.room.clone();
+    let message = ServerMessage::Chat(line);
+    let mut evicted = Vec::new();
+    for (&id, client) in &mut state.clients {
+        if client.room != room {
+            continue;
+        }
+        if let Err(e) = client.send(&message) {
+            evicted.push((id, e.to_string()));
+        }
+    }
+    for (id, reason) in evicted {
+        evict(state, id, &reason);
+    }
+
+    Ok(())
+}
+
+// Oublier une image qui n'est plus l'avatar d'aucun client
+fn release_image(state: &mut ServerState, hash: &ImageHash) {
+    if !state
+        .clients
+        .values()
+        .any(|client| client.image.as_ref() == Some(hash))
+    {
+        state.images.remove(hash);
+    }
+}
+
+// Connexion coupée : le joueur reste dans le monde le temps que le client
+// reprenne sa session, sauf si la reprise est désactivée
+fn handle_disconnect(
+    client_id: u32,
+    connection: u64,
+    state: &mut ServerState,
+) {
+    // Session déjà reprise sur une autre connexion
+    if !is_current(state, client_id, connection) {
+        return;
+    }
+    let Some(grace) = state.session_grace else {
+        if let Some(mut client) = remove_client(state, client_id) {
+            close_connection(&mut client);
+            println!("Client {} déconnecté et supprimé.", client_id);
+        }
+        return;
+    };
+
+    if let Some(client) = state.clients.get_mut(&client_id) {
+        close_connection(client);
+        client.inputs.clear();
+        client.udp = None;
+        client.detached = Some(Instant::now());
+        println!(
+            "Client {} déconnecté, session conservée pendant {:?}.",
+            client_id, grace
+        );
+    }
+}
+
+// Départ volontaire : pas de session à conserver
+fn handle_leave(client_id: u32, state: &mut ServerState) {
+    if let Some(mut client) = remove_client(state, client_id) {
+        close_connection(&mut client);
+        println!("Client {} parti.", client_id);
+    }
+}
+
+fn close_connection(client: &mut ClientInfo) {
+    if let Some(mut outbox) = client.outbox.take() {
+        // Répondre à la fermeture d'un client web
+        outbox.close(client.websocket.then(websocket::close_frame));
+    }
+}
+
+// Déconnecter un client qui ne suit plus, en lui indiquant pourquoi si sa
+// connexion le permet encore
+fn evict(state: &mut ServerState, client_id: u32, reason: &str) {
+    if let Some(mut client) = remove_client(state, client_id) {
+        eprintln!("Client {} évincé : {}", client_id, reason);
+        let kicked = ServerMessage::Kicked(reason.to_string());
+        let farewell =
+            encode_message(client.websocket, client.framing, &kicked).ok();
+        if let Some(outbox) = client.outbox.as_mut() {
+            outbox.close(farewell);
+        }
+    }
+}
+
+fn remove_client(state: &mut ServerState, client_id: u32) -> Option<ClientInfo> {
+    let client = state.clients.remove(&client_id)?;
+    if !client.violations.is_empty() {
+        println!(
+            "Client {} : messages refusés {}",
+            client_id,
+            errors::summary(&client.violations)
+        );
+    }
+    state.positions.remove(&client_id);
+    if let Some(hash) = &client.image {
+        release_image(state, hash);
+    }
+    Some(client)
+}
+
+// Outils communs aux tests des modules du serveur : un état sans réseau et
+// des joueurs placés directement, sans connexion
+#[cfg(test)]
+mod testing {
+    use super::*;
+
+    pub fn state(configure: impl FnOnce(&mut ServerConfig)) -> ServerState {
+        let mut config = ServerConfig::default();
+        configure(&mut config);
+        ServerState::new(&config, None)
+    }
+
+    // Joueur déconnecté (sans file d'envoi) dans le salon `room`
+    pub fn add_player(
+        state: &mut ServerState,
+        client_id: u32,
+        room: &str,
+        position: Point,
+        image: Option<Image>,
+    ) {
+        let image = image.map(|image| {
+            let hash = image.hash();
+            state.images.entry(hash.clone()).or_insert(image);
+            hash
+        });
+        state.clients.insert(
+            client_id,
+            ClientInfo {
+                position,
+                image,
+                name: None,
+                room: room.to_string(),
+                outbox: None,
+                framing: Framing::Lines,
+                websocket: false,
+                acked: None,
+                inputs: VecDeque::new(),
+                last_input: 0,
+                udp_token: 0,
+                udp: None,
+                members: None,
+                last_seen: Instant::now(),
+                session: session::new_token(),
+                connection: 0,
+                detached: None,
+                invalid: VecDeque::new(),
+                violations: HashMap::new(),
+                motions: TokenBucket::new(state.motion_policy.burst),
+                chats: TokenBucket::new(state.chat_policy.burst),
+            },
+        );
+        state.next_id = state.next_id.max(client_id + 1);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Nombre de messages en attente d'envoi au-delà duquel le client est
+// considéré comme trop lent (environ quatre secondes d'instantanés à 30 pas/s)
+const OUTBOX_CAPACITY: usize = 128;
+
+// Durée maximale d'une écriture bloquée par un client qui ne lit plus
+const MAX_LAG: Duration = Duration::from_secs(2);
+
+// File d'envoi d'un client : les diffusions y déposent les messages déjà
+// encodés sans jamais bloquer
+#[derive(Debug)]
+pub enum Outbox {
+    // Un thread dédié écrit les messages sur la connexion
+    Threaded {
+        sender: Option<SyncSender<Vec<u8>>>,
+        stream: TcpStream,
+        farewell: Arc<Mutex<Option<Vec<u8>>>>, // Dernier message avant la fermeture
+        writer: JoinHandle<()>,
+    },
+    // Connexion non bloquante : la boucle d'événements écrit les messages
+    // quand le système l'accepte (voir `flush`)
+    Buffered {
+        stream: TcpStream,
+        queue: VecDeque<Vec<u8>>,
+        written: usize, // Octets déjà écrits du premier message
+        blocked_since: Option<Instant>, // Première écriture refusée depuis le dernier progrès
+    },
+}
+
+impl Outbox {
+    pub fn new(
+        client_id: u32,
+        stream: &TcpStream,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (sender, receiver) =
+            mpsc::sync_channel::<Vec<u8>>(OUTBOX_CAPACITY);
+        let farewell = Arc::new(Mutex::new(None::<Vec<u8>>));
+        let mut output = stream.try_clone()?;
+        output.set_write_timeout(Some(MAX_LAG))?;
+
+        let closing = Arc::clone(&farewell);
+        let writer = std::thread::spawn(move || {
+            for bytes in receiver {
+                // Client évincé : abandonner les messages en attente
+                if closing.lock().unwrap().is_some() {
+                    break;
+                }
+                let written =
+                    output.write_all(&bytes).and_then(|_| output.flush());
+                if let Err(e) = written {
+                    eprintln!(
+                        "Client {} : envoi impossible depuis plus de {:?} : {}",
+                        client_id, MAX_LAG, e
+                    );
+                    break;
+                }
+            }
+            if let Some(bytes) = closing.lock().unwrap().take() {
+                let _ = output.write_all(&bytes).and_then(|_| output.flush());
+            }
+            // Débloque aussi le thread de lecture du client
+            let _ = output.shutdown(Shutdown::Both);
+        });
+
+        Ok(Outbox::Threaded {
+            sender: Some(sender),
+            stream: stream.try_clone()?,
+            farewell,
+            writer,
+        })
+    }
+
+    // File sans thread pour une connexion déjà non bloquante
+    pub fn buffered(
+        stream: &TcpStream,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Outbox::Buffered {
+            stream: stream.try_clone()?,
+            queue: VecDeque::new(),
+            written: 0,
+            blocked_since: None,
+        })
+    }
+
+    // Déposer un message sans attendre ; une erreur indique que le client
+    // ne suit plus et doit être déconnecté
+    pub fn push(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        match self {
+            Outbox::Threaded { sender: None, .. } => {
+                Err("connexion en cours de fermeture".to_string())
+            }
+            Outbox::Threaded {
+                sender: Some(sender),
+                ..
+            } => sender.try_send(bytes).map_err(|e| match e {
+                TrySendError::Full(_) => full(),
+                TrySendError::Disconnected(_) => {
+                    "connexion interrompue".to_string()
+                }
+            }),
+            Outbox::Buffered { queue, .. } => {
+                if queue.len() >= OUTBOX_CAPACITY {
+                    return Err(full());
+                }
+                queue.push_back(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    // Des messages attendent que la connexion soit prête en écriture
+    pub fn is_pending(&self) -> bool {
+        match self {
+            Outbox::Threaded { .. } => false,
+            Outbox::Buffered { queue, .. } => !queue.is_empty(),
+        }
+    }
+
+    // Écrire ce que la connexion accepte sans bloquer ; une erreur indique
+    // que le client ne lit plus depuis trop longtemps
+    pub fn flush(&mut self) -> Result<(), String> {
+        let Outbox::Buffered {
+            stream,
+            queue,
+            written,
+            blocked_since,
+        } = self
+        else {
+            return Ok(());
+        };
+        while let Some(bytes) = queue.front() {
+            match stream.write(&bytes[*written..]) {
+                Ok(0) => return Err("connexion interrompue".to_string()),
+                Ok(len) => {
+                    *blocked_since = None;
+                    *written += len;
+                    if *written == bytes.len() {
+                        queue.pop_front();
+                        *written = 0;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    let since = *blocked_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() > MAX_LAG {
+                        return Err(format!(
+                            "envoi impossible depuis plus de {:?}",
+                            MAX_LAG
+                        ));
+                    }
+                    break;
+                }
+                Err(e) => return Err(format!("connexion interrompue : {}", e)),
+            }
+        }
+        Ok(())
+    }
+
+    // Fermer la connexion après un dernier message éventuel, sans attendre
+    // l'envoi des messages encore en file
+    pub fn close(&mut self, farewell: Option<Vec<u8>>) {
+        match self {
+            Outbox::Threaded {
+                sender,
+                stream,
+                farewell: closing,
+                ..
+            } => {
+                *closing.lock().unwrap() = Some(farewell.unwrap_or_default());
+                *sender = None;
+                // Le thread de lecture ne recevra plus rien
+                let _ = stream.shutdown(Shutdown::Read);
+            }
+            Outbox::Buffered {
+                stream,
+                queue,
+                written,
+                ..
+            } => {
+                // Terminer le message entamé pour ne pas couper une trame,
+                // puis tenter le dernier message une seule fois
+                let mut bytes = match queue.front() {
+                    Some(partial) if *written > 0 => partial[*written..].to_vec(),
+                    _ => Vec::new(),
+                };
+                bytes.extend(farewell.unwrap_or_default());
+                let _ = stream.write_all(&bytes);
+                queue.clear();
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    // Ne plus accepter de messages ; ceux déjà en file partent quand même
+    // (voir `drain`)
+    pub fn finish(&mut self) {
+        if let Outbox::Threaded { sender, .. } = self {
+            *sender = None;
+        }
+    }
+
+    // Attendre l'envoi des messages en file, au plus jusqu'à `deadline`,
+    // puis fermer la connexion ; `false` si des messages n'ont pas pu partir
+    pub fn drain(&mut self, deadline: Instant) -> bool {
+        let sent = loop {
+            let done = match self {
+                Outbox::Threaded { writer, .. } => writer.is_finished(),
+                Outbox::Buffered { .. } => match self.flush() {
+                    Ok(()) => !self.is_pending(),
+                    Err(_) => break false,
+                },
+            };
+            if done {
+                break true;
+            }
+            if Instant::now() >= deadline {
+                break false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let (Outbox::Threaded { stream, .. } | Outbox::Buffered { stream, .. }) =
+            self;
+        let _ = stream.shutdown(Shutdown::Both);
+        sent
+    }
+}
+
+fn full() -> String {
+    format!(
+        "file d'envoi pleine ({} messages en attente)",
+        OUTBOX_CAPACITY
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // Connexion du serveur vers un client qui ne lit que quand on le lui
+    // demande ; le client doit rester ouvert pendant le test
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = TcpStream::connect(address).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (server, client)
+    }
+
+    // Assez gros pour remplir les tampons du système en quelques messages
+    fn message() -> Vec<u8> {
+        vec![b'x'; 256 * 1024]
+    }
+
+    // Déposer des messages jusqu'au refus ; retourne leur nombre et l'erreur
+    fn fill(outbox: &mut Outbox) -> (usize, String) {
+        for count in 0..10 * OUTBOX_CAPACITY {
+            if let Err(e) = outbox.push(message()) {
+                return (count, e);
+            }
+        }
+        panic!("file d'envoi jamais pleine");
+    }
+
+    #[test]
+    fn push_only_enqueues() {
+        let (server, mut client) = connection();
+        server.set_nonblocking(true).unwrap();
+        client.set_nonblocking(true).unwrap();
+        let mut outbox = Outbox::buffered(&server).unwrap();
+        outbox.push(b"ping 1\n".to_vec()).unwrap();
+        assert!(outbox.is_pending());
+        let mut buffer = [0u8; 16];
+        let e = client.read(&mut buffer).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+
+        outbox.flush().unwrap();
+        assert!(!outbox.is_pending());
+        client.set_nonblocking(false).unwrap();
+        assert_eq!(client.read(&mut buffer).unwrap(), 7);
+        assert_eq!(&buffer[..7], b"ping 1\n");
+    }
+
+    #[test]
+    fn full_queue_is_refused_with_a_reason() {
+        let (server, _client) = connection();
+        server.set_nonblocking(true).unwrap();
+        let mut outbox = Outbox::buffered(&server).unwrap();
+        assert_eq!(fill(&mut outbox), (OUTBOX_CAPACITY, full()));
+
+        // Le thread d'envoi, bloqué sur un client qui ne lit pas, ne ralentit
+        // pas les dépôts : la file finit par être refusée, sans attendre
+        let (server, _client) = connection();
+        let mut outbox = Outbox::new(1, &server).unwrap();
+        let started = Instant::now();
+        let (count, reason) = fill(&mut outbox);
+        assert!(count >= OUTBOX_CAPACITY, "{}", count);
+        assert_eq!(reason, full());
+        assert!(started.elapsed() < MAX_LAG);
+        outbox.close(None);
+    }
+
+    #[test]
+    fn client_behind_for_too_long_is_refused() {
+        let (server, _client) = connection();
+        server.set_nonblocking(true).unwrap();
+        let mut outbox = Outbox::buffered(&server).unwrap();
+        for _ in 0..OUTBOX_CAPACITY {
+            outbox.push(message()).unwrap();
+        }
+        // Les tampons du système sont pleins : le retard commence
+        outbox.flush().unwrap();
+        assert!(outbox.is_pending());
+        outbox.flush().unwrap();
+        // Faire comme si le client n'avait rien lu depuis `MAX_LAG`
+        if let Outbox::Buffered { blocked_since, .. } = &mut outbox {
+            let since = blocked_since.expect("écriture jamais refusée");
+            *blocked_since = Some(since - MAX_LAG);
+        }
+        let reason = outbox.flush().unwrap_err();
+        assert!(reason.starts_with("envoi impossible"), "{}", reason);
+    }
+
+    #[test]
+    fn stalled_writer_closes_the_queue() {
+        let (server, _client) = connection();
+        let mut outbox = Outbox::new(1, &server).unwrap();
+        fill(&mut outbox);
+        // Le thread d'envoi abandonne après `MAX_LAG` sans progrès ; le
+        // système accepte encore quelques octets de temps en temps, ce qui
+        // peut repousser l'abandon de quelques secondes
+        let deadline = Instant::now() + 10 * MAX_LAG;
+        let reason = loop {
+            assert!(Instant::now() < deadline, "thread d'envoi jamais arrêté");
+            std::thread::sleep(Duration::from_millis(100));
+            match outbox.push(message()) {
+                Err(e) if e != full() => break e,
+                _ => {}
+            }
+        };
+        assert_eq!(reason, "connexion interrompue");
+    }
+}
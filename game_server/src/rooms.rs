@@ -0,0 +1,312 @@
+use crate::snapshot::SnapshotHistory;
+use crate::{errors, ServerState};
+use game_protocol::{
+    check_room_name, max_position, ErrorCode, Image, Point, RoomInfo,
+    RoomRequest, ServerMessage,
+};
+use rand::Rng;
+
+// Salon où arrivent les joueurs et où ils reviennent en quittant un autre
+// salon ; il n'est jamais supprimé
+pub const LOBBY: &str = "accueil";
+
+// Dimensions acceptées pour le monde du salon d'accueil ; les autres salons
+// vont de `MIN_SIDE` à la taille de l'accueil, pour tenir dans la fenêtre du
+// client natif, qui garde la taille reçue à la connexion
+pub const MIN_SIDE: usize = 100;
+pub const MAX_SIDE: usize = 4096;
+
+// Un monde indépendant : ses joueurs ne voient que les autres joueurs du
+// même salon et restent dans ses limites
+#[derive(Debug)]
+pub struct Room {
+    pub name: String, // Nom affiché, tel que choisi à la création
+    pub width: usize,
+    pub height: usize,
+    pub snapshots: SnapshotHistory, // Derniers états du salon envoyés à ses joueurs
+}
+
+impl Room {
+    pub fn new(name: &str, width: usize, height: usize) -> Self {
+        Room {
+            name: name.to_string(),
+            width,
+            height,
+            snapshots: SnapshotHistory::new(),
+        }
+    }
+
+    // Position maximale d'un joueur dont l'avatar est `sprite`, pour qu'il
+    // reste entier dans le monde
+    pub fn max_position(&self, sprite: Option<&Image>) -> Point {
+        let (width, height) = sprite.map_or((0, 0), |s| (s.width, s.height));
+        max_position(self.width, self.height, width, height)
+    }
+
+    // Position d'arrivée tirée au hasard, l'avatar `sprite` entier dans le
+    // monde du salon
+    pub fn spawn_point(&self, sprite: Option<&Image>) -> Point {
+        let max = self.max_position(sprite);
+        let mut rng = rand::thread_rng();
+        Point {
+            x: rng.gen_range(0..=max.x),
+            y: rng.gen_range(0..=max.y),
+        }
+    }
+}
+
+// Clé d'un salon dans `ServerState::rooms` : deux salons ne peuvent pas
+// différer seulement par la casse
+pub fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+fn info(state: &ServerState, key: &str) -> Option<RoomInfo> {
+    let room = state.rooms.get(key)?;
+    Some(RoomInfo {
+        name: room.name.clone(),
+        width: room.width,
+        height: room.height,
+        players: state.clients.values().filter(|c| c.room == key).count(),
+    })
+}
+
+// Envoyer la liste des salons, le salon d'accueil en tête
+pub fn list(state: &mut ServerState, client_id: u32) {
+    let mut keys: Vec<&String> = state.rooms.keys().collect();
+    keys.sort_by_key(|key| (key.as_str() != LOBBY, key.as_str()));
+    let rooms = keys.iter().filter_map(|key| info(state, key)).collect();
+    send(state, client_id, &ServerMessage::Rooms(rooms));
+}
+
+// Créer un salon à la demande d'un client, qui le rejoint aussitôt ; un
+// refus n'est pas un abus et ne compte pas comme message invalide
+pub fn create(state: &mut ServerState, client_id: u32, request: RoomRequest) {
+    let lobby = &state.rooms[LOBBY];
+    let width = request.width.unwrap_or(lobby.width);
+    let height = request.height.unwrap_or(lobby.height);
+    let key = key(&request.name);
+
+    let refused = if let Err(e) = check_room_name(&request.name) {
+        Some(e)
+    } else if state.rooms.contains_key(&key) {
+        Some(format!("le salon « {} » existe déjà", request.name))
+    } else if let Some(max) =
+        state.max_rooms.filter(|max| state.rooms.len() > *max)
+    {
+        Some(format!("trop de salons ouverts (au plus {})", max))
+    } else if !(MIN_SIDE..=lobby.width).contains(&width)
+        || !(MIN_SIDE..=lobby.height).contains(&height)
+    {
+        Some(format!(
+            "monde de {}×{}, de {}×{} à {}×{} pixels (taille de l'accueil)",
+            width,
+            height,
+            MIN_SIDE,
+            MIN_SIDE,
+            lobby.width,
+            lobby.height
+        ))
+    } else {
+        None
+    };
+    if let Some(reason) = refused {
+        let code = ErrorCode::InvalidRoom;
+        errors::reply(state, client_id, code, "create_room", reason);
+        return;
+    }
+
+    state
+        .rooms
+        .insert(key.clone(), Room::new(&request.name, width, height));
+    println!(
+        "Salon « {} » ({}×{}) créé par le client {}",
+        request.name, width, height, client_id
+    );
+    join(state, client_id, &key);
+}
+
+// Rejoindre un salon existant, désigné par son nom
+pub fn join_by_name(state: &mut ServerState, client_id: u32, name: &str) {
+    let key = key(name);
+    if !state.rooms.contains_key(&key) {
+        let reason = format!("aucun salon nommé « {} »", name);
+        errors::reply(
+            state,
+            client_id,
+            ErrorCode::UnknownRoom,
+            "join_room",
+            reason,
+        );
+        return;
+    }
+    join(state, client_id, &key);
+}
+
+// Revenir au salon d'accueil
+pub fn leave(state: &mut ServerState, client_id: u32) {
+    join(state, client_id, LOBBY);
+}
+
+// Déplacer un joueur dans le salon `key` ; il y apparaît à une position
+// tirée au hasard et reçoit un état complet de son nouveau monde au prochain
+// pas. Rejoindre son propre salon ne fait que renvoyer sa description
+fn join(state: &mut ServerState, client_id: u32, key: &str) {
+    let (Some(client), Some(room)) =
+        (state.clients.get_mut(&client_id), state.rooms.get(key))
+    else {
+        return;
+    };
+    if client.room != key {
+        client.room = key.to_string();
+        if let Some(hash) = &client.image {
+            client.position = room.spawn_point(state.images.get(hash));
+        }
+        // Les instantanés déjà acquittés appartiennent à l'ancien salon
+        client.acked = None;
+        client.members = None;
+        println!("Client {} : salon « {} » rejoint", client_id, room.name);
+    }
+    if let Some(info) = info(state, key) {
+        send(state, client_id, &ServerMessage::Room(info));
+    }
+}
+
+// Supprimer les salons que tous les joueurs ont quittés (y compris ceux qui
+// pouvaient encore reprendre leur session) ; appelée à chaque pas
+pub fn clean_up(state: &mut ServerState) {
+    let clients = &state.clients;
+    state.rooms.retain(|key, room| {
+        let used =
+            key == LOBBY || clients.values().any(|client| client.room == *key);
+        if !used {
+            println!("Salon « {} » supprimé : plus aucun joueur", room.name);
+        }
+        used
+    });
+}
+
+fn send(state: &mut ServerState, client_id: u32, message: &ServerMessage) {
+    let Some(client) = state.clients.get_mut(&client_id) else {
+        return;
+    };
+    if let Err(e) = client.send(message) {
+        crate::evict(state, client_id, &e.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::time::Instant;
+
+    fn request(name: &str, size: Option<(usize, usize)>) -> RoomRequest {
+        RoomRequest {
+            name: name.to_string(),
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+        }
+    }
+
+    // Serveur au monde de 800×600, avec les joueurs 1 à `players` à
+    // l'accueil
+    fn state(players: u32, max_rooms: Option<usize>) -> ServerState {
+        let mut state = testing::state(|config| config.max_rooms = max_rooms);
+        let origin = Point { x: 0, y: 0 };
+        for id in 1..=players {
+            testing::add_player(&mut state, id, LOBBY, origin, None);
+        }
+        state
+    }
+
+    fn room_of(state: &ServerState, client_id: u32) -> &str {
+        &state.clients[&client_id].room
+    }
+
+    #[test]
+    fn room_names_are_checked() {
+        let mut state = state(1, None);
+        for name in ["", " Donjon", "Don  jon", "Donjon!", &"x".repeat(100)] {
+            create(&mut state, 1, request(name, None));
+        }
+        assert_eq!(state.rooms.len(), 1);
+        assert_eq!(room_of(&state, 1), LOBBY);
+
+        create(&mut state, 1, request("Le Donjon", None));
+        assert_eq!(room_of(&state, 1), "le donjon");
+        let room = &state.rooms["le donjon"];
+        assert_eq!(room.name, "Le Donjon");
+        // Sans dimensions, le salon reprend celles de l'accueil
+        assert_eq!((room.width, room.height), (800, 600));
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut state = state(2, None);
+        create(&mut state, 1, request("Donjon", None));
+        create(&mut state, 2, request("DONJON", None));
+        assert_eq!(state.rooms.len(), 2);
+        assert_eq!(room_of(&state, 2), LOBBY);
+
+        join_by_name(&mut state, 2, "dOnJoN");
+        assert_eq!(room_of(&state, 2), "donjon");
+        join_by_name(&mut state, 2, "Grenier");
+        assert_eq!(room_of(&state, 2), "donjon");
+        leave(&mut state, 2);
+        assert_eq!(room_of(&state, 2), LOBBY);
+    }
+
+    #[test]
+    fn sizes_go_from_min_side_to_the_lobby_size() {
+        let mut state = state(1, None);
+        for size in [(99, 300), (300, 99), (801, 300), (300, 601)] {
+            create(&mut state, 1, request("Trop", Some(size)));
+        }
+        assert_eq!(state.rooms.len(), 1);
+
+        create(&mut state, 1, request("Petit", Some((MIN_SIDE, MIN_SIDE))));
+        let room = &state.rooms["petit"];
+        assert_eq!((room.width, room.height), (MIN_SIDE, MIN_SIDE));
+        create(&mut state, 1, request("Grand", Some((800, 600))));
+        let room = &state.rooms["grand"];
+        assert_eq!((room.width, room.height), (800, 600));
+    }
+
+    #[test]
+    fn max_rooms_does_not_count_the_lobby() {
+        let mut state = state(3, Some(2));
+        create(&mut state, 1, request("Un", None));
+        create(&mut state, 2, request("Deux", None));
+        create(&mut state, 3, request("Trois", None));
+        assert_eq!(state.rooms.len(), 3);
+        assert_eq!(room_of(&state, 3), LOBBY);
+
+        // Une place se libère quand un salon se vide
+        leave(&mut state, 1);
+        clean_up(&mut state);
+        create(&mut state, 3, request("Trois", None));
+        assert_eq!(room_of(&state, 3), "trois");
+    }
+
+    #[test]
+    fn empty_rooms_are_removed_but_not_the_lobby() {
+        let mut state = state(2, None);
+        create(&mut state, 1, request("Donjon", None));
+        join_by_name(&mut state, 2, "Donjon");
+        clean_up(&mut state);
+        assert!(state.rooms.contains_key(LOBBY));
+
+        leave(&mut state, 1);
+        clean_up(&mut state);
+        assert!(state.rooms.contains_key("donjon"));
+        // Un joueur déconnecté, encore dans le salon, le garde ouvert
+        state.clients.get_mut(&2).unwrap().detached = Some(Instant::now());
+        clean_up(&mut state);
+        assert!(state.rooms.contains_key("donjon"));
+        // Même vide, l'accueil reste
+        state.clients.clear();
+        clean_up(&mut state);
+        assert_eq!(state.rooms.keys().collect::<Vec<_>>(), [LOBBY]);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::ServerState;
+use rand::Rng;
+use std::time::Instant;
+
+// Jeton de session : 128 bits aléatoires en hexadécimal (une chaîne, que
+// le client web peut manipuler sans perte de précision)
+pub fn new_token() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+// Client d'une session encore valide, connecté ou non
+pub fn find(state: &ServerState, token: &str) -> Option<u32> {
+    state.session_grace?;
+    state
+        .clients
+        .iter()
+        .find(|(_, client)| client.session == token)
+        .map(|(&id, _)| id)
+}
+
+// Supprimer les joueurs dont la connexion n'a pas été reprise à temps ;
+// appelée à chaque pas de simulation
+pub fn expire(state: &mut ServerState) {
+    let Some(grace) = state.session_grace else {
+        return;
+    };
+    let now = Instant::now();
+    let expired: Vec<u32> = state
+        .clients
+        .iter()
+        .filter(|(_, client)| {
+            client
+                .detached
+                .is_some_and(|since| now.saturating_duration_since(since) > grace)
+        })
+        .map(|(&id, _)| id)
+        .collect();
+
+    // Les autres joueurs verront le départ dans le prochain instantané
+    for id in expired {
+        crate::remove_client(state, id);
+        println!("Session du client {} expirée, joueur supprimé.", id);
+    }
+}
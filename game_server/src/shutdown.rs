@@ -0,0 +1,147 @@
+use crate::ServerState;
+use game_protocol::{ServerMessage, ServerShutdown};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Temps laissé aux clients pour recevoir les messages en file avant l'arrêt
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Raison annoncée aux clients après SIGINT ou SIGTERM
+const SIGNAL_REASON: &str = "signal d'arrêt reçu";
+
+// Raison par défaut de la commande `shutdown`
+const ADMIN_REASON: &str = "arrêt demandé par l'administrateur";
+
+// Signal reçu ; le gestionnaire ne peut rien faire de plus qu'une écriture
+// atomique, la boucle de simulation s'occupe du reste
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+// Arrêt demandé depuis la console d'administration
+static REQUEST: Mutex<Option<ServerShutdown>> = Mutex::new(None);
+
+// SIGINT et SIGTERM demandent un arrêt propre ; un second signal arrête le
+// serveur immédiatement si l'arrêt propre ne se termine pas
+#[cfg(unix)]
+pub fn install_signal_handlers() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        if SIGNALED.swap(true, Ordering::SeqCst) {
+            // SAFETY: `_exit` peut être appelée depuis un gestionnaire de
+            // signal
+            unsafe { libc::_exit(130) };
+        }
+    }
+    let handler =
+        on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: le gestionnaire n'utilise que des opérations autorisées dans
+    // un gestionnaire de signal
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_signal_handlers() {}
+
+// Lire les commandes d'administration sur l'entrée standard :
+// `shutdown [--restart S] [raison...]`
+pub fn listen_console() {
+    std::thread::spawn(|| {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.split_first() {
+                Some((&"shutdown", args)) => match parse_shutdown(args) {
+                    Ok(notice) => *REQUEST.lock().unwrap() = Some(notice),
+                    Err(e) => eprintln!("Commande 'shutdown' invalide : {}", e),
+                },
+                Some((command, _)) => eprintln!(
+                    "Commande inconnue '{}' (disponible : shutdown \
+                     [--restart S] [raison])",
+                    command
+                ),
+                None => {}
+            }
+        }
+    });
+}
+
+fn parse_shutdown(args: &[&str]) -> Result<ServerShutdown, String> {
+    let (restart_in, reason) = match args {
+        ["--restart", seconds, reason @ ..] => {
+            let seconds = seconds
+                .parse::<u64>()
+                .map_err(|_| format!("délai invalide '{}'", seconds))?;
+            (Some(seconds), reason)
+        }
+        ["--restart"] => return Err("délai manquant après --restart".into()),
+        reason => (None, reason),
+    };
+    let reason = match reason.join(" ") {
+        reason if reason.is_empty() => ADMIN_REASON.to_string(),
+        reason => reason,
+    };
+    Ok(ServerShutdown { reason, restart_in })
+}
+
+// Arrêt demandé par un signal ou par la console, vérifié par la boucle
+// principale du cœur utilisé
+pub fn requested() -> Option<ServerShutdown> {
+    if let Some(notice) = REQUEST.lock().unwrap().take() {
+        return Some(notice);
+    }
+    SIGNALED.load(Ordering::SeqCst).then(|| ServerShutdown {
+        reason: SIGNAL_REASON.to_string(),
+        restart_in: None,
+    })
+}
+
+// Prévenir tous les clients, puis attendre que leurs files d'envoi se vident
+// (au plus `DRAIN_TIMEOUT`) avant de fermer les connexions. Les nouveaux
+// clients sont refusés dès le début de l'arrêt
+pub fn shut_down(state: &mut ServerState, notice: ServerShutdown) {
+    state.closing = true;
+    match notice.restart_in {
+        Some(seconds) => println!(
+            "Arrêt du serveur : {} (redémarrage prévu dans {} s)",
+            notice.reason, seconds
+        ),
+        None => println!("Arrêt du serveur : {}", notice.reason),
+    }
+
+    let message = ServerMessage::ServerShutdown(notice);
+    for (id, client) in &mut state.clients {
+        // Une file pleine n'empêche pas d'envoyer ce qui s'y trouve déjà
+        if let Err(e) = client.send(&message) {
+            eprintln!("Client {} : arrêt non annoncé : {}", id, e);
+        }
+        let websocket = client.websocket;
+        if let Some(outbox) = client.outbox.as_mut() {
+            if websocket {
+                let _ = outbox.push(crate::websocket::close_frame());
+            }
+            outbox.finish();
+        }
+    }
+
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    let mut unsent = 0;
+    for client in state.clients.values_mut() {
+        if let Some(mut outbox) = client.outbox.take() {
+            if !outbox.drain(deadline) {
+                unsent += 1;
+            }
+        }
+    }
+    if unsent > 0 {
+        eprintln!(
+            "{} client(s) n'ont pas reçu tous leurs messages avant l'arrêt",
+            unsent
+        );
+    }
+    println!("Serveur arrêté.");
+}
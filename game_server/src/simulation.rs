@@ -0,0 +1,70 @@
+use crate::{
+    collision, heartbeat, rooms, session, shutdown, snapshot, ServerState,
+    SharedServerState,
+};
+use game_protocol::apply_motion;
+use std::time::{Duration, Instant};
+
+// Boucle de simulation : à chaque pas, appliquer les déplacements reçus
+// depuis le pas précédent puis diffuser un seul instantané. C'est aussi elle
+// qui arrête le serveur quand un arrêt est demandé
+pub fn run(state: SharedServerState, tick_rate: f64) {
+    let period = Duration::from_secs_f64(1.0 / tick_rate);
+    let mut next_tick = Instant::now() + period;
+    loop {
+        std::thread::sleep(next_tick.saturating_duration_since(Instant::now()));
+        next_tick += period;
+
+        let mut state = state.lock().unwrap();
+        if let Some(notice) = shutdown::requested() {
+            shutdown::shut_down(&mut state, notice);
+            std::process::exit(0);
+        }
+        tick(&mut state);
+    }
+}
+
+// Un pas de simulation suivi de la diffusion de son résultat
+pub fn tick(state: &mut ServerState) {
+    heartbeat::check(state);
+    session::expire(state);
+    step(state);
+    rooms::clean_up(state);
+    snapshot::broadcast(state);
+}
+
+fn step(state: &mut ServerState) {
+    // Traiter les clients dans l'ordre de leurs identifiants pour que le
+    // résultat ne dépende pas de l'ordre d'arrivée des messages
+    let mut ids: Vec<u32> = state.clients.keys().copied().collect();
+    ids.sort();
+
+    for id in ids {
+        let client = &state.clients[&id];
+        if client.inputs.is_empty() {
+            continue;
+        }
+        // Chaque joueur reste dans les limites de son salon, son avatar
+        // entièrement visible, et bute sur les autres joueurs si les
+        // collisions sont activées. Un déplacement bloqué est tout de même
+        // acquitté : le client se recale sur la position renvoyée
+        let sprite = client.image.as_ref().and_then(|h| state.images.get(h));
+        let max = state.rooms[&client.room].max_position(sprite);
+        let mut position = client.position;
+        let mut last_input = client.last_input;
+        for motion in &client.inputs {
+            let target = apply_motion(position, motion.delta, max);
+            position = collision::resolve(state, id, position, target);
+            last_input = motion.seq;
+        }
+
+        let client = state.clients.get_mut(&id).unwrap();
+        client.inputs.clear();
+        client.position = position;
+        client.last_input = last_input;
+        println!(
+            "Client {} déplacé vers la nouvelle position {:?}",
+            id, client.position
+        );
+    }
+}
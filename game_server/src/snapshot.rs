@@ -0,0 +1,417 @@
+use crate::{ClientInfo, ServerState};
+use game_protocol::{
+    Framing, ImageHash, PlayerState, Point, Positions, ServerMessage, Snapshot,
+};
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, UdpSocket};
+
+// Nombre d'instantanés conservés ; un client dont le dernier acquittement est
+// plus ancien (ou qui n'a encore rien acquitté) reçoit l'état complet
+const SNAPSHOT_HISTORY: usize = 32;
+
+// Ce que les autres voient d'un joueur, en dehors de sa position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    image: ImageHash,
+    name: Option<String>,
+}
+
+// Vue du monde transmise aux clients : profil et position de chaque joueur
+// ayant envoyé son image
+pub type WorldView = HashMap<u32, (Profile, Point)>;
+
+// Vue du monde sans les positions, pour les clients qui les reçoivent par UDP
+pub type Members = HashMap<u32, Profile>;
+
+#[derive(Debug)]
+struct RecordedView {
+    seq: u64,
+    time: u64, // Millisecondes écoulées depuis le démarrage du serveur
+    view: WorldView,
+}
+
+// Derniers états d'un salon ; les numéros sont communs à tous les salons,
+// pour qu'un acquittement venu d'un autre salon ne désigne jamais un état
+// de celui-ci
+#[derive(Debug)]
+pub struct SnapshotHistory {
+    views: VecDeque<RecordedView>,
+}
+
+impl SnapshotHistory {
+    pub fn new() -> Self {
+        SnapshotHistory {
+            views: VecDeque::new(),
+        }
+    }
+
+    // Enregistrer l'état courant du monde sous le numéro `seq`
+    fn record(&mut self, seq: u64, time: u64, view: WorldView) {
+        self.views.push_back(RecordedView { seq, time, view });
+        while self.views.len() > SNAPSHOT_HISTORY {
+            self.views.pop_front();
+        }
+    }
+
+    // Instantané à envoyer à un client dont le dernier acquittement est
+    // `acked` ; `None` s'il est déjà à jour
+    pub fn snapshot_for(&self, acked: Option<u64>) -> Option<Snapshot> {
+        let current = self.views.back()?;
+        let base = acked
+            .and_then(|acked| self.views.iter().find(|base| base.seq == acked));
+        let (base_seq, players, left) = match base {
+            Some(base) if base.seq == current.seq => return None,
+            Some(base) => {
+                let (players, left) = diff(&base.view, &current.view);
+                (Some(base.seq), players, left)
+            }
+            None => {
+                // Client trop en retard : resynchronisation complète
+                let (players, _) = diff(&WorldView::new(), &current.view);
+                (None, players, Vec::new())
+            }
+        };
+        Some(Snapshot {
+            seq: current.seq,
+            base: base_seq,
+            time: current.time,
+            players,
+            left,
+            last_input: None,
+        })
+    }
+}
+
+// Joueurs arrivés ou modifiés (seulement les champs qui ont changé) et
+// joueurs partis entre `base` et `current`, triés par identifiant
+fn diff(base: &WorldView, current: &WorldView) -> (Vec<PlayerState>, Vec<u32>) {
+    let mut players: Vec<PlayerState> = current
+        .iter()
+        .filter_map(|(&id, (profile, position))| {
+            let (base_profile, position) = match base.get(&id) {
+                None => (None, Some(*position)),
+                Some((base_profile, base_position)) => (
+                    Some(base_profile),
+                    (base_position != position).then_some(*position),
+                ),
+            };
+            let player = changes(id, base_profile, profile, position);
+            let unchanged = player.image.is_none()
+                && player.name.is_none()
+                && player.position.is_none();
+            (!unchanged).then_some(player)
+        })
+        .collect();
+    players.sort_by_key(|p| p.id);
+
+    let mut left: Vec<u32> = base
+        .keys()
+        .filter(|id| !current.contains_key(id))
+        .copied()
+        .collect();
+    left.sort();
+
+    (players, left)
+}
+
+// État d'un joueur limité aux champs de son profil qui diffèrent de `base`
+// (tous pour un joueur qui vient d'arriver)
+fn changes(
+    id: u32,
+    base: Option<&Profile>,
+    profile: &Profile,
+    position: Option<Point>,
+) -> PlayerState {
+    let image = base.map(|base| &base.image);
+    let name = base.map(|base| &base.name);
+    PlayerState {
+        id,
+        image: (image != Some(&profile.image)).then(|| profile.image.clone()),
+        name: profile.name.clone().filter(|_| name != Some(&profile.name)),
+        position,
+    }
+}
+
+// Arrivées, départs et changements de profil depuis le dernier état envoyé
+// à un client UDP ; TCP garantit qu'il l'a reçu, pas besoin d'acquittement
+fn members_snapshot(
+    seq: u64,
+    time: u64,
+    sent: Option<&(u64, Members)>,
+    view: &WorldView,
+) -> Option<Snapshot> {
+    let empty = Members::new();
+    let (base, known) = match sent {
+        Some((base, known)) => (Some(*base), known),
+        None => (None, &empty),
+    };
+
+    let mut players: Vec<PlayerState> = view
+        .iter()
+        .filter_map(|(&id, (profile, position))| match known.get(&id) {
+            Some(known_profile) if known_profile == profile => None,
+            Some(known_profile) => {
+                Some(changes(id, Some(known_profile), profile, None))
+            }
+            // Position initiale, en attendant les datagrammes
+            None => Some(changes(id, None, profile, Some(*position))),
+        })
+        .collect();
+    players.sort_by_key(|p| p.id);
+
+    let mut left: Vec<u32> = known
+        .keys()
+        .filter(|id| !view.contains_key(id))
+        .copied()
+        .collect();
+    left.sort();
+
+    if base.is_some() && players.is_empty() && left.is_empty() {
+        return None;
+    }
+    Some(Snapshot {
+        seq,
+        base,
+        time,
+        players,
+        left,
+        last_input: None,
+    })
+}
+
+// Enregistrer l'état courant de chaque salon et envoyer à chacun de ses
+// joueurs ce qui a changé depuis son dernier acquittement ; un instantané
+// part à chaque pas, même immobile, pour que les clients puissent dater les
+// positions reçues. Les clients UDP reçoivent les positions par datagramme
+// et seulement les arrivées et départs par TCP
+pub fn broadcast(state: &mut ServerState) {
+    let time = state.started.elapsed().as_millis() as u64;
+    let mut rooms: Vec<String> = state.rooms.keys().cloned().collect();
+    rooms.sort();
+
+    let mut evicted = Vec::new();
+    for room in rooms {
+        evicted.extend(broadcast_room(state, &room, time));
+    }
+    for (id, reason) in evicted {
+        crate::evict(state, id, &reason);
+    }
+}
+
+// Diffuser l'état d'un salon ; retourne les clients qui ne suivent plus
+fn broadcast_room(
+    state: &mut ServerState,
+    key: &str,
+    time: u64,
+) -> Vec<(u32, String)> {
+    let view: WorldView = state
+        .clients
+        .iter()
+        .filter(|(_, client)| client.room == key)
+        .filter_map(|(&id, client)| {
+            let profile = Profile {
+                image: client.image.clone()?,
+                name: client.name.clone(),
+            };
+            Some((id, (profile, client.position)))
+        })
+        .collect();
+    let Some(room) = state.rooms.get_mut(key) else {
+        return Vec::new();
+    };
+    state.last_seq += 1;
+    let seq = state.last_seq;
+    room.snapshots.record(seq, time, view.clone());
+
+    let mut positions: Vec<(u32, Point)> = view
+        .iter()
+        .map(|(&id, (_, position))| (id, *position))
+        .collect();
+    positions.sort_by_key(|(id, _)| *id);
+
+    let mut evicted = Vec::new();
+    for (&id, client) in &mut state.clients {
+        // Un client déconnecté recevra l'état complet à la reprise
+        if client.room != key || client.detached.is_some() {
+            continue;
+        }
+        let result = match (client.udp, &state.udp) {
+            (Some(addr), Some(socket)) => {
+                let positions = Positions {
+                    seq,
+                    time,
+                    players: positions.clone(),
+                    last_input: (client.last_input > 0)
+                        .then_some(client.last_input),
+                };
+                // Un datagramme perdu n'est pas grave : pas d'éviction
+                if let Err(e) = send_positions(socket, addr, positions) {
+                    eprintln!(
+                        "Erreur lors de l'envoi des positions au client {} : {}",
+                        id, e
+                    );
+                }
+                send_members(client, seq, time, &view)
+            }
+            _ => match room.snapshots.snapshot_for(client.acked) {
+                Some(mut snapshot) => {
+                    snapshot.last_input =
+                        (client.last_input > 0).then_some(client.last_input);
+                    client.send(&ServerMessage::Snapshot(snapshot))
+                }
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = result {
+            evicted.push((id, e.to_string()));
+        }
+    }
+    evicted
+}
+
+fn send_positions(
+    socket: &UdpSocket,
+    addr: SocketAddr,
+    positions: Positions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let datagram =
+        ServerMessage::Positions(positions).encode(Framing::Binary)?;
+    socket.send_to(&datagram, addr)?;
+    Ok(())
+}
+
+fn send_members(
+    client: &mut ClientInfo,
+    seq: u64,
+    time: u64,
+    view: &WorldView,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshot =
+        members_snapshot(seq, time, client.members.as_ref(), view);
+    if let Some(snapshot) = snapshot {
+        client.send(&ServerMessage::Snapshot(snapshot))?;
+        let members = view
+            .iter()
+            .map(|(&id, (profile, _))| (id, profile.clone()))
+            .collect();
+        client.members = Some((seq, members));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u64) -> ImageHash {
+        ImageHash::try_from(format!("{:016x}", n)).unwrap()
+    }
+
+    fn player(image: u64, name: Option<&str>, x: i32) -> (Profile, Point) {
+        let profile = Profile {
+            image: hash(image),
+            name: name.map(str::to_string),
+        };
+        (profile, Point { x, y: 0 })
+    }
+
+    // Trois joueurs enregistrés sous le numéro 1, puis `current` sous le 2
+    fn history(current: WorldView) -> SnapshotHistory {
+        let base = WorldView::from([
+            (1, player(1, Some("Alice"), 0)),
+            (2, player(2, None, 0)),
+            (3, player(3, Some("Carole"), 0)),
+        ]);
+        let mut history = SnapshotHistory::new();
+        history.record(1, 100, base);
+        history.record(2, 150, current);
+        history
+    }
+
+    fn state(id: u32) -> PlayerState {
+        PlayerState {
+            id,
+            image: None,
+            name: None,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn delta_lists_arrivals_and_departures() {
+        let history = history(WorldView::from([
+            (1, player(1, Some("Alice"), 0)),
+            (3, player(3, Some("Carole"), 0)),
+            (4, player(4, Some("David"), 7)),
+        ]));
+        let snapshot = history.snapshot_for(Some(1)).unwrap();
+        assert_eq!((snapshot.seq, snapshot.base), (2, Some(1)));
+        assert_eq!(snapshot.time, 150);
+        let arrival = PlayerState {
+            image: Some(hash(4)),
+            name: Some("David".to_string()),
+            position: Some(Point { x: 7, y: 0 }),
+            ..state(4)
+        };
+        assert_eq!(snapshot.players, vec![arrival]);
+        assert_eq!(snapshot.left, vec![2]);
+    }
+
+    #[test]
+    fn delta_only_carries_changed_fields() {
+        let history = history(WorldView::from([
+            (1, player(1, Some("Alice"), 5)),
+            (2, player(2, Some("Bob"), 0)),
+            (3, player(5, Some("Carole"), 0)),
+        ]));
+        let snapshot = history.snapshot_for(Some(1)).unwrap();
+        let moved = PlayerState {
+            position: Some(Point { x: 5, y: 0 }),
+            ..state(1)
+        };
+        let renamed = PlayerState {
+            name: Some("Bob".to_string()),
+            ..state(2)
+        };
+        let new_image = PlayerState {
+            image: Some(hash(5)),
+            ..state(3)
+        };
+        assert_eq!(snapshot.players, vec![moved, renamed, new_image]);
+        assert!(snapshot.left.is_empty());
+    }
+
+    #[test]
+    fn unknown_or_missing_ack_gets_the_full_state() {
+        let mut history = history(WorldView::from([
+            (1, player(1, Some("Alice"), 0)),
+        ]));
+        let full = PlayerState {
+            image: Some(hash(1)),
+            name: Some("Alice".to_string()),
+            position: Some(Point { x: 0, y: 0 }),
+            ..state(1)
+        };
+        let snapshot = history.snapshot_for(None).unwrap();
+        assert_eq!(snapshot.base, None);
+        assert_eq!(snapshot.players, vec![full.clone()]);
+        assert!(snapshot.left.is_empty());
+
+        // L'état 1 finit par sortir de l'historique
+        for seq in 3..3 + SNAPSHOT_HISTORY as u64 {
+            let view = WorldView::from([(1, player(1, Some("Alice"), 0))]);
+            history.record(seq, seq * 50, view);
+        }
+        let snapshot = history.snapshot_for(Some(1)).unwrap();
+        assert_eq!(snapshot.base, None);
+        assert_eq!(snapshot.players, vec![full]);
+        assert!(snapshot.left.is_empty());
+    }
+
+    #[test]
+    fn up_to_date_client_gets_nothing() {
+        let history = history(WorldView::new());
+        assert_eq!(history.snapshot_for(Some(2)), None);
+        assert_eq!(SnapshotHistory::new().snapshot_for(None), None);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{ServerState, SharedServerState};
+use game_protocol::{ClientMessage, Frame, UdpHello};
+use std::net::{SocketAddr, UdpSocket};
+
+// Taille maximale d'un datagramme UDP
+pub const MAX_DATAGRAM: usize = 65_536;
+
+// Réception des datagrammes des clients : `udp_hello` associe une adresse à
+// un client, puis ses déplacements arrivent par ce canal
+pub fn run(socket: UdpSocket, state: SharedServerState) {
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) => handle_datagram(
+                &buffer[..len],
+                from,
+                &mut state.lock().unwrap(),
+            ),
+            Err(e) => eprintln!("Erreur de réception UDP : {}", e),
+        }
+    }
+}
+
+// Traiter un datagramme reçu d'un client
+pub fn handle_datagram(
+    datagram: &[u8],
+    from: SocketAddr,
+    state: &mut ServerState,
+) {
+    let message = Frame::from_datagram(datagram)
+        .map_err(|e| e.into())
+        .and_then(|frame| ClientMessage::decode(&frame));
+    match message {
+        Ok(ClientMessage::UdpHello(hello)) => {
+            handle_udp_hello(hello, from, state)
+        }
+        Ok(ClientMessage::Motion(motion)) => {
+            let client_id = state
+                .clients
+                .iter()
+                .find(|(_, client)| client.udp == Some(from))
+                .map(|(&id, _)| id);
+            // Mêmes limites que par TCP, les refus sont signalés par TCP
+            match client_id {
+                Some(client_id) => {
+                    let _ = crate::handle_motion(client_id, motion, state);
+                }
+                None => eprintln!(
+                    "Déplacement UDP d'une adresse inconnue : {}",
+                    from
+                ),
+            }
+        }
+        Ok(_) => eprintln!("Message inattendu par UDP depuis {}", from),
+        Err(e) => eprintln!("Datagramme mal formaté depuis {} : {}", from, e),
+    }
+}
+
+fn handle_udp_hello(
+    hello: UdpHello,
+    from: SocketAddr,
+    state: &mut ServerState,
+) {
+    let Some(client) = state
+        .clients
+        .get_mut(&hello.client_id)
+        .filter(|client| client.udp_token == hello.token)
+    else {
+        eprintln!("'udp_hello' refusé depuis {}", from);
+        return;
+    };
+
+    // Le client répète `udp_hello` tant qu'il n'a rien reçu par UDP
+    if client.udp != Some(from) {
+        client.udp = Some(from);
+        // Les arrivées et départs continuent par TCP, mais sans les
+        // positions : repartir d'un état complet
+        client.members = None;
+        println!(
+            "Client {} : canal UDP ouvert depuis {}",
+            hello.client_id, from
+        );
+    }
+}
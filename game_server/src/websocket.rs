@@ -0,0 +1,478 @@
+use crate::HELLO_TIMEOUT;
+use game_protocol::{base64, Framing, MAX_FRAME_LEN};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Instant;
+
+// Page servie aux navigateurs qui ne demandent pas de WebSocket
+const INDEX_HTML: &str = include_str!("../web/index.html");
+
+// Constante ajoutée à la clé du client pour calculer `Sec-WebSocket-Accept`
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Taille maximale de l'en-tête de la requête HTTP
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+// Poignée de main HTTP : `true` si la connexion est passée en WebSocket ;
+// sinon la page du client web (ou une erreur) a été envoyée
+pub fn accept(
+    stream: &mut TcpStream,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let request = read_request(stream)?;
+    let mut lines = request.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let headers: Vec<(String, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| *value)
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    if method != Some("GET") {
+        respond(stream, "405 Method Not Allowed", "text/plain", "")?;
+        return Ok(false);
+    }
+
+    let upgrade = header("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let Some(key) = header("sec-websocket-key").filter(|_| upgrade) else {
+        // Simple requête HTTP : servir le client web
+        match path {
+            Some("/") | Some("/index.html") => respond(
+                stream,
+                "200 OK",
+                "text/html; charset=utf-8",
+                INDEX_HTML,
+            )?,
+            _ => respond(stream, "404 Not Found", "text/plain", "")?,
+        }
+        return Ok(false);
+    };
+    if header("sec-websocket-version") != Some("13") {
+        respond(stream, "426 Upgrade Required", "text/plain", "")?;
+        return Ok(false);
+    }
+
+    let accept =
+        base64::encode(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    stream.flush()?;
+    Ok(true)
+}
+
+// Lire l'en-tête de la requête octet par octet pour ne rien consommer
+// au-delà ; la requête entière doit arriver dans `HELLO_TIMEOUT`
+fn read_request(
+    stream: &mut TcpStream,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + HELLO_TIMEOUT;
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_LEN {
+            return Err("Requête HTTP trop longue".into());
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err("Requête HTTP trop lente".into());
+        }
+        stream.set_read_timeout(Some(left))?;
+        match stream.read(&mut byte) {
+            Ok(0) => {
+                return Err("Connexion fermée pendant la requête HTTP".into())
+            }
+            Ok(_) => request.push(byte[0]),
+            Err(e) if crate::is_timeout(&e) => {
+                return Err("Requête HTTP trop lente".into())
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(String::from_utf8(request)?)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<(), Error> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// Envelopper un message encodé dans un message WebSocket : texte pour les
+// lignes, binaire pour les trames binaires
+pub fn message_frame(framing: Framing, payload: &[u8]) -> Vec<u8> {
+    let opcode = match framing {
+        Framing::Lines => OPCODE_TEXT,
+        Framing::Binary => OPCODE_BINARY,
+    };
+    frame(opcode, payload)
+}
+
+pub fn close_frame() -> Vec<u8> {
+    frame(OPCODE_CLOSE, &[])
+}
+
+// Les trames du serveur ne sont pas masquées
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 10);
+    bytes.push(0x80 | opcode); // FIN
+    match payload.len() {
+        len if len < 126 => bytes.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            bytes.push(126);
+            bytes.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            bytes.push(127);
+            bytes.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+// Flux des messages reçus d'un client WebSocket, mis bout à bout pour
+// `FrameDecoder` : chaque message contient une trame du protocole (un
+// retour à la ligne est ajouté aux messages texte qui n'en ont pas). Les
+// pings reçoivent leur pong dans `pongs`
+pub struct Reader<R, W> {
+    inner: R,
+    pongs: W,
+    message: Vec<u8>,
+    position: usize,
+    closed: bool,
+}
+
+impl<R: Read, W: Write> Reader<R, W> {
+    pub fn new(inner: R, pongs: W) -> Self {
+        Reader {
+            inner,
+            pongs,
+            message: Vec::new(),
+            position: 0,
+            closed: false,
+        }
+    }
+
+    // Prochain message de données ; `None` à la fermeture de la connexion
+    fn read_message(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut message = Vec::new();
+        let mut kind = None; // Texte ou binaire, d'après la première trame
+        loop {
+            let mut header = [0u8; 2];
+            if self.inner.read(&mut header[..1])? == 0 {
+                return Ok(None);
+            }
+            self.inner.read_exact(&mut header[1..])?;
+            let fin = header[0] & 0x80 != 0;
+            let opcode = header[0] & 0x0f;
+            if header[1] & 0x80 == 0 {
+                return Err(invalid_data(
+                    "Trame WebSocket du client non masquée",
+                ));
+            }
+            let len = match header[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    self.inner.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as usize
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    self.inner.read_exact(&mut len)?;
+                    u64::from_be_bytes(len) as usize
+                }
+                len => len as usize,
+            };
+            if len > MAX_FRAME_LEN - message.len() {
+                return Err(invalid_data("Message WebSocket trop long"));
+            }
+            let mut mask = [0u8; 4];
+            self.inner.read_exact(&mut mask)?;
+            let mut payload = vec![0u8; len];
+            self.inner.read_exact(&mut payload)?;
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= mask[i % 4];
+            }
+
+            match opcode {
+                OPCODE_CLOSE => return Ok(None),
+                // Un ping reçoit un pong avec les mêmes données (RFC 6455,
+                // section 5.5.2) ; les pongs spontanés sont ignorés
+                OPCODE_PING => {
+                    if !fin || len > 125 {
+                        return Err(invalid_data("Ping WebSocket invalide"));
+                    }
+                    self.pongs.write_all(&frame(OPCODE_PONG, &payload))?;
+                    self.pongs.flush()?;
+                    continue;
+                }
+                OPCODE_PONG => continue,
+                OPCODE_TEXT | OPCODE_BINARY if kind.is_none() => {
+                    kind = Some(opcode);
+                }
+                OPCODE_CONTINUATION if kind.is_some() => {}
+                _ => {
+                    return Err(invalid_data(format!(
+                        "Trame WebSocket inattendue (opcode {})",
+                        opcode
+                    )))
+                }
+            }
+            message.extend_from_slice(&payload);
+            if fin {
+                break;
+            }
+        }
+        if kind == Some(OPCODE_TEXT) && !message.ends_with(b"\n") {
+            message.push(b'\n');
+        }
+        Ok(Some(message))
+    }
+}
+
+impl<R: Read, W: Write> Read for Reader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.position == self.message.len() {
+            if self.closed {
+                return Ok(0);
+            }
+            match self.read_message()? {
+                Some(message) => {
+                    self.message = message;
+                    self.position = 0;
+                }
+                None => self.closed = true,
+            }
+        }
+        let len = buf.len().min(self.message.len() - self.position);
+        let end = self.position + len;
+        buf[..len].copy_from_slice(&self.message[self.position..end]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+// SHA-1 (RFC 3174), seulement pour `Sec-WebSocket-Accept`
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] =
+        [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Trame telle que l'envoie un navigateur : masquée, de longueur quelconque
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut bytes = frame(opcode, payload);
+        let header_len = bytes.len() - payload.len();
+        if !fin {
+            bytes[0] &= 0x7f;
+        }
+        bytes[1] |= 0x80;
+        let masked = payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]);
+        bytes.truncate(header_len);
+        bytes.extend_from_slice(&mask);
+        bytes.extend(masked);
+        bytes
+    }
+
+    fn read_all(input: Vec<u8>) -> (Result<Vec<u8>, Error>, Vec<u8>) {
+        let mut pongs = Vec::new();
+        let mut reader = Reader::new(Cursor::new(input), &mut pongs);
+        let mut output = Vec::new();
+        let result = reader.read_to_end(&mut output).map(|_| output);
+        (result, pongs)
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_6455_example() {
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let accept = base64::encode(&sha1(
+            format!("{}{}", key, WEBSOCKET_GUID).as_bytes(),
+        ));
+        assert_eq!(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn sha1_matches_rfc_3174_examples() {
+        let hex = |digest: [u8; 20]| {
+            digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        };
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn frame_lengths_use_7_16_or_64_bits() {
+        for (len, header) in [
+            (0, vec![0x81, 0]),
+            (125, vec![0x81, 125]),
+            (126, vec![0x81, 126, 0, 126]),
+            (65535, vec![0x81, 126, 0xff, 0xff]),
+            (65536, vec![0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]),
+        ] {
+            let payload = vec![b'a'; len];
+            let bytes = frame(OPCODE_TEXT, &payload);
+            assert_eq!(bytes[..header.len()], header[..], "longueur {}", len);
+            assert_eq!(bytes[header.len()..], payload[..]);
+        }
+        assert_eq!(message_frame(Framing::Binary, b"x")[0], 0x82);
+        assert_eq!(close_frame(), vec![0x88, 0]);
+    }
+
+    #[test]
+    fn masked_frames_of_every_length_are_unmasked() {
+        for len in [0, 1, 125, 126, 65535, 65536] {
+            let payload: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let input = client_frame(true, OPCODE_BINARY, &payload);
+            let (result, _) = read_all(input);
+            assert_eq!(result.unwrap(), payload, "longueur {}", len);
+        }
+    }
+
+    #[test]
+    fn fragments_are_reassembled_and_text_gets_a_newline() {
+        let mut input = client_frame(false, OPCODE_TEXT, b"move ");
+        input.extend(client_frame(false, OPCODE_CONTINUATION, b"{\"x\":1,"));
+        input.extend(client_frame(true, OPCODE_CONTINUATION, b"\"y\":2}"));
+        input.extend(client_frame(true, OPCODE_TEXT, b"bye\n"));
+        input.extend(client_frame(true, OPCODE_CLOSE, &[]));
+        input.extend(client_frame(true, OPCODE_TEXT, b"ignored"));
+        let (result, _) = read_all(input);
+        assert_eq!(result.unwrap(), b"move {\"x\":1,\"y\":2}\nbye\n");
+    }
+
+    #[test]
+    fn pings_are_answered_with_the_same_payload() {
+        let mut input = client_frame(false, OPCODE_TEXT, b"he");
+        input.extend(client_frame(true, OPCODE_PING, b"abc"));
+        input.extend(client_frame(true, OPCODE_CONTINUATION, b"llo"));
+        input.extend(client_frame(true, OPCODE_PONG, b"spontaneous"));
+        let (result, pongs) = read_all(input);
+        assert_eq!(result.unwrap(), b"hello\n");
+        assert_eq!(pongs, frame(OPCODE_PONG, b"abc"));
+    }
+
+    #[test]
+    fn invalid_frames_are_refused() {
+        let invalid = |input: Vec<u8>| {
+            let (result, _) = read_all(input);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        };
+        // Non masquée
+        invalid(frame(OPCODE_TEXT, b"hello"));
+        // Suite sans début de message, début au milieu d'un message
+        invalid(client_frame(true, OPCODE_CONTINUATION, b"x"));
+        let mut input = client_frame(false, OPCODE_TEXT, b"x");
+        input.extend(client_frame(true, OPCODE_BINARY, b"y"));
+        invalid(input);
+        // Ping fragmenté ou trop long
+        invalid(client_frame(false, OPCODE_PING, b"x"));
+        invalid(client_frame(true, OPCODE_PING, &[0; 126]));
+        // Trop long, même en plusieurs fragments
+        let mut header = client_frame(true, OPCODE_BINARY, &[]);
+        header[1] = 0x80 | 127;
+        header.splice(2..2, ((MAX_FRAME_LEN + 1) as u64).to_be_bytes());
+        invalid(header);
+        let half = vec![0; MAX_FRAME_LEN / 2 + 1];
+        let mut input = client_frame(false, OPCODE_BINARY, &half);
+        input.extend(client_frame(true, OPCODE_CONTINUATION, &half));
+        invalid(input);
+    }
+}